@@ -1,69 +1,281 @@
 extern crate aoc2020;
 
 use aoc2020::utils;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::env;
+use std::ops::Range;
 
-fn find_paths(index: usize, numbers: &Vec<usize>, memoized: &mut Vec<Option<usize>>) -> usize {
-    let mut next_index = index + 1;
-    let mut paths = 0;
+struct AdapterChain {
+    // Sorted joltages, starting with the outlet at 0 and ending with the device
+    joltages: Vec<usize>,
+    max_gap: usize,
+}
+
+impl AdapterChain {
+    fn new(adapters: &[usize], max_gap: usize, device_offset: usize) -> Result<AdapterChain, String> {
+        let mut joltages: Vec<usize> = adapters.to_vec();
+        joltages.sort_unstable();
+
+        let device = joltages.last().unwrap_or(&0) + device_offset;
+        joltages.insert(0, 0);
+        joltages.push(device);
+
+        for pair in joltages.windows(2) {
+            if pair[1] - pair[0] > max_gap {
+                return Err(format!(
+                    "gap of {} between {} and {} is larger than the tolerance of {}",
+                    pair[1] - pair[0], pair[0], pair[1], max_gap
+                ));
+            }
+        }
+
+        Ok(AdapterChain { joltages, max_gap })
+    }
+
+    fn differences(&self) -> BTreeMap<usize, usize> {
+        let mut counts: BTreeMap<usize, usize> = BTreeMap::new();
+
+        for pair in self.joltages.windows(2) {
+            *counts.entry(pair[1] - pair[0]).or_insert(0) += 1;
+        }
+
+        counts
+    }
+
+    // Indices of the joltages that can be plugged into joltages[index]
+    fn next_indices(&self, index: usize) -> Range<usize> {
+        let mut end = index + 1;
+        while end < self.joltages.len() && self.joltages[end] <= self.joltages[index] + self.max_gap {
+            end += 1;
+        }
+
+        (index + 1)..end
+    }
+
+    // paths[i] is the number of ways to get from joltages[i] to the device, built from the device
+    // backwards so long chains don't recurse. With a modulus the counts are reduced as they go,
+    // otherwise an overflow is an error.
+    fn find_paths(&self, modulus: Option<u128>) -> Result<Vec<u128>, String> {
+        if modulus == Some(0) {
+            return Err(String::from("modulus must be positive"));
+        }
+
+        let last = self.joltages.len() - 1;
+        let mut paths: Vec<u128> = vec![0; self.joltages.len()];
+        paths[last] = 1 % modulus.unwrap_or(u128::MAX);
+
+        for index in (0..last).rev() {
+            for next_index in self.next_indices(index) {
+                paths[index] = match modulus {
+                    Some(m) => add_mod(paths[index], paths[next_index], m),
+                    None => paths[index].checked_add(paths[next_index])
+                        .ok_or_else(|| format!("arrangement count overflows u128 at joltage {}", self.joltages[index]))?,
+                };
+            }
+        }
+
+        Ok(paths)
+    }
+
+    fn count_arrangements(&self) -> Result<u128, String> {
+        Ok(self.find_paths(None)?[0])
+    }
+
+    fn count_arrangements_mod(&self, modulus: u128) -> Result<u128, String> {
+        Ok(self.find_paths(Some(modulus))?[0])
+    }
 
-    if index == numbers.len() - 1 {
-        // Successful end path found
-        return 1;
+    // next_indices in the order that keeps arrangements lexicographic. Going straight to the
+    // device ends the arrangement, and a list sorts before any longer list it starts.
+    fn ordered_next_indices(&self, index: usize) -> Vec<usize> {
+        let last = self.joltages.len() - 1;
+        let mut next: Vec<usize> = self.next_indices(index).collect();
+        if next.last() == Some(&last) {
+            next.rotate_right(1);
+        }
+
+        next
     }
 
-    if memoized[index].is_some() {
-        return memoized[index].unwrap();
+    // Every arrangement in lexicographic order, as the list of adapters used, stopping after limit
+    fn arrangements(&self, limit: usize) -> Vec<Vec<usize>> {
+        let mut found: Vec<Vec<usize>> = Vec::new();
+        let mut chain: Vec<usize> = Vec::new();
+        self.collect_arrangements(0, &mut chain, &mut found, limit);
+
+        found
+    }
+
+    fn collect_arrangements(&self, index: usize, chain: &mut Vec<usize>, found: &mut Vec<Vec<usize>>, limit: usize) {
+        if index == self.joltages.len() - 1 {
+            found.push(chain.clone());
+            return;
+        }
+
+        for next_index in self.ordered_next_indices(index) {
+            if found.len() >= limit {
+                return;
+            }
+
+            let is_device = next_index == self.joltages.len() - 1;
+            if !is_device {
+                chain.push(self.joltages[next_index]);
+            }
+            self.collect_arrangements(next_index, chain, found, limit);
+            if !is_device {
+                chain.pop();
+            }
+        }
     }
 
-    while next_index < numbers.len() {
-        if numbers[next_index] <= numbers[index] + 3 {
-            paths += find_paths(next_index, numbers, memoized);
+    // The k-th (0-based) arrangement in lexicographic order, found by skipping over whole subtrees
+    // using the path counts rather than enumerating them
+    fn nth_arrangement(&self, k: u128) -> Result<Vec<usize>, String> {
+        let paths = self.find_paths(None)?;
+        if k >= paths[0] {
+            return Err(format!("only {} arrangements", paths[0]));
         }
-        next_index += 1;
+
+        let last = self.joltages.len() - 1;
+        let mut remaining = k;
+        let mut index = 0;
+        let mut chain: Vec<usize> = Vec::new();
+
+        while index != last {
+            for next_index in self.ordered_next_indices(index) {
+                if remaining < paths[next_index] {
+                    index = next_index;
+                    break;
+                }
+                remaining -= paths[next_index];
+            }
+
+            if index != last {
+                chain.push(self.joltages[index]);
+            }
+        }
+
+        Ok(chain)
     }
 
-    memoized[index] = Some(paths);
-    paths
+    // Uniformly random arrangement, picking a random rank and looking it up
+    fn random_arrangement(&self, seed: u64) -> Result<Vec<usize>, String> {
+        let total = self.count_arrangements()?;
+        let mut rng = XorShift::new(seed);
+
+        self.nth_arrangement(rng.next_below(total))
+    }
 }
 
-fn find_paths_begin(numbers: &Vec<usize>) -> usize {
-    let mut memoized: Vec<Option<usize>> = vec![Option::None; numbers.len()];
+// (a + b) mod m for a and b already below m, without overflowing for moduli near u128::MAX
+fn add_mod(a: u128, b: u128, m: u128) -> u128 {
+    if a >= m - b {
+        a - (m - b)
+    } else {
+        a + b
+    }
+}
 
-    let mut paths = 0;
+// xorshift64*, plenty for picking arrangements and keeps the results reproducible from a seed
+struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        // One round of splitmix64 so every seed gives its own state
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
 
-    let mut index = 0;
-    while index < numbers.len() && numbers[index] <= 3 {
-        paths += find_paths(index, numbers, &mut memoized);
-        index += 1;
+        // The state must never be zero
+        XorShift { state: if z == 0 { 0x9E37_79B9_7F4A_7C15 } else { z } }
     }
 
-    paths
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // Rejection sampling so every value below bound is equally likely
+    fn next_below(&mut self, bound: u128) -> u128 {
+        let zone = u128::MAX - (u128::MAX % bound);
+        loop {
+            let value = ((self.next() as u128) << 64) | self.next() as u128;
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
 }
 
-fn main() {
-    let mut numbers: Vec<usize> = utils::read_lines("./input_data/10.txt")
-        .iter().map(|l| l.parse::<usize>().unwrap()).collect();
+fn format_arrangement(arrangement: &[usize]) -> String {
+    arrangement.iter().map(|j| j.to_string()).collect::<Vec<String>>().join(",")
+}
+
+// e.g. "--max-gap 4 --device-offset 1 --modulus 1000000007" or "--nth 5"
+fn run(args: &[String]) -> Result<(), String> {
+    let max_gap = utils::parse_arg::<usize>(args, "--max-gap")?.unwrap_or(3);
+    let device_offset = utils::parse_arg::<usize>(args, "--device-offset")?.unwrap_or(3);
+    let modulus = utils::parse_arg::<u128>(args, "--modulus")?;
+    let list = utils::parse_arg::<usize>(args, "--list")?;
+    let nth = utils::parse_arg::<u128>(args, "--nth")?;
+    let random = utils::parse_arg::<u64>(args, "--random")?;
 
-    numbers.sort();
+    let numbers: Vec<usize> = utils::read_lines("./input_data/10.txt")
+        .iter()
+        .enumerate()
+        .map(|(i, l)| l.parse::<usize>().map_err(|e| format!("line {}: bad joltage '{}': {}", i + 1, l, e)))
+        .collect::<Result<_, _>>()?;
 
-    let mut counts: HashMap<usize, usize> = HashMap::new();
+    let chain = AdapterChain::new(&numbers, max_gap, device_offset).map_err(|e| format!("Impossible chain: {}", e))?;
 
-    // Add the initial jump
-    counts.insert(numbers[0], 1);
+    if let Some(limit) = list {
+        for arrangement in chain.arrangements(limit) {
+            println!("{}", format_arrangement(&arrangement));
+        }
+        return Ok(());
+    }
+
+    if let Some(k) = nth {
+        println!("{}", format_arrangement(&chain.nth_arrangement(k)?));
+        return Ok(());
+    }
 
-    // Add the final jump
-    *counts.entry(3).or_insert(0) += 1;
+    if let Some(seed) = random {
+        println!("{}", format_arrangement(&chain.random_arrangement(seed)?));
+        return Ok(());
+    }
+
+    let counts = chain.differences();
+    for (diff, count) in &counts {
+        println!("{}-jolt differences: {}", diff, count);
+    }
 
-    for i in 0..(numbers.len() - 1) {
-        let diff = numbers[i + 1] - numbers[i];
+    println!("Part 1: {}", counts.get(&1).unwrap_or(&0) * counts.get(&3).unwrap_or(&0));
 
-        *counts.entry(diff).or_insert(0) += 1;
+    match modulus {
+        Some(m) => match chain.count_arrangements_mod(m) {
+            Ok(count) => println!("Part 2: {} (mod {})", count, m),
+            Err(e) => println!("Part 2: {}", e),
+        },
+        None => match chain.count_arrangements() {
+            Ok(count) => println!("Part 2: {}", count),
+            Err(e) => println!("Part 2: {}", e),
+        },
     }
 
-    println!("Part 1: {}", counts.get(&1).unwrap() * counts.get(&3).unwrap());
+    Ok(())
+}
 
-    println!("Part 2: {}", find_paths_begin(&numbers));
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+    }
 }
 
 
@@ -71,24 +283,154 @@ fn main() {
 mod tests {
     use super::*;
 
-    fn find_paths_test(numbers: &mut Vec<usize>) -> usize {
-        numbers.sort();
-        find_paths_begin(numbers)
+    fn find_paths_test(numbers: &[usize]) -> u128 {
+        AdapterChain::new(numbers, 3, 3).unwrap().count_arrangements().unwrap()
     }
 
     #[test]
     fn test_simple() {
-        assert_eq!(find_paths_test(&mut vec![1]), 1);
-        assert_eq!(find_paths_test(&mut vec![1, 2]), 2);
+        assert_eq!(find_paths_test(&[1]), 1);
+        assert_eq!(find_paths_test(&[1, 2]), 2);
         // 123, 13, 23, 3
-        assert_eq!(find_paths_test(&mut vec![1, 2, 3]), 4);
+        assert_eq!(find_paths_test(&[1, 2, 3]), 4);
         // 1234, 124, 134, 14, 234, 24, 34
-        assert_eq!(find_paths_test(&mut vec![1, 2, 3, 4]), 7);
+        assert_eq!(find_paths_test(&[1, 2, 3, 4]), 7);
+    }
+
+    #[test]
+    fn test_differences() {
+        let chain = AdapterChain::new(&[16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4], 3, 3).unwrap();
+        let counts = chain.differences();
+        assert_eq!(counts.get(&1), Some(&7));
+        assert_eq!(counts.get(&3), Some(&5));
+        assert_eq!(counts.len(), 2);
+
+        let chain = AdapterChain::new(&[3, 6, 9], 3, 3).unwrap();
+        assert_eq!(chain.differences().get(&1), None);
+    }
+
+    #[test]
+    fn test_tolerance() {
+        assert!(AdapterChain::new(&[1, 5], 3, 3).is_err());
+        assert!(AdapterChain::new(&[1, 2], 3, 4).is_err());
+        assert!(AdapterChain::new(&[4], 3, 3).is_err());
+
+        // 0, 1, 2, 3 with a device at 4: every arrangement ends with a gap of at most 2
+        let chain = AdapterChain::new(&[1, 2, 3], 2, 1).unwrap();
+        assert_eq!(chain.count_arrangements(), Ok(5));
+    }
+
+    #[test]
+    fn test_arrangements() {
+        let chain = AdapterChain::new(&[1, 2, 3, 4], 3, 3).unwrap();
+        let all = chain.arrangements(usize::MAX);
+        assert_eq!(all, vec![
+            vec![1, 2, 3, 4],
+            vec![1, 2, 4],
+            vec![1, 3, 4],
+            vec![1, 4],
+            vec![2, 3, 4],
+            vec![2, 4],
+            vec![3, 4],
+        ]);
+        assert_eq!(chain.arrangements(2).len(), 2);
+
+        for (k, arrangement) in all.iter().enumerate() {
+            assert_eq!(&chain.nth_arrangement(k as u128).unwrap(), arrangement);
+        }
+        assert!(chain.nth_arrangement(7).is_err());
+    }
+
+    #[test]
+    fn test_arrangements_device_reachable_early() {
+        // The device is one above the last adapter, so it can be reached from 1, 2 and 3
+        let chain = AdapterChain::new(&[1, 2, 3], 3, 1).unwrap();
+        let all = chain.arrangements(usize::MAX);
+        assert_eq!(&all[..3], &[vec![1], vec![1, 2], vec![1, 2, 3]]);
+
+        let chain = AdapterChain::new(&[1, 2, 3, 5, 6, 7, 9], 4, 2).unwrap();
+        let all = chain.arrangements(usize::MAX);
+        let mut sorted = all.clone();
+        sorted.sort();
+        assert_eq!(all, sorted);
+        assert_eq!(all.len() as u128, chain.count_arrangements().unwrap());
+
+        for (k, arrangement) in all.iter().enumerate() {
+            assert_eq!(&chain.nth_arrangement(k as u128).unwrap(), arrangement);
+        }
+    }
+
+    #[test]
+    fn test_nth_matches_enumeration() {
+        let chain = AdapterChain::new(&[16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4], 3, 3).unwrap();
+        let all = chain.arrangements(usize::MAX);
+        assert_eq!(all.len(), 8);
+        assert_eq!(all[0], vec![1, 4, 5, 6, 7, 10, 11, 12, 15, 16, 19]);
+
+        for (k, arrangement) in all.iter().enumerate() {
+            assert_eq!(&chain.nth_arrangement(k as u128).unwrap(), arrangement);
+        }
+    }
+
+    #[test]
+    fn test_random_arrangement() {
+        let chain = AdapterChain::new(&[1, 2, 3, 4], 3, 3).unwrap();
+        let all = chain.arrangements(usize::MAX);
+
+        assert_eq!(chain.random_arrangement(42), chain.random_arrangement(42));
+
+        let mut seen = vec![0; all.len()];
+        for seed in 0..700 {
+            let arrangement = chain.random_arrangement(seed).unwrap();
+            seen[all.iter().position(|a| *a == arrangement).unwrap()] += 1;
+        }
+        assert!(seen.iter().all(|count| *count > 50));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let adapters: Vec<usize> = (1..=200).collect();
+        let chain = AdapterChain::new(&adapters, 3, 3).unwrap();
+        assert!(chain.count_arrangements().is_err());
+
+        // Tribonacci, so small enough to check by hand
+        let adapters: Vec<usize> = (1..=10).collect();
+        let chain = AdapterChain::new(&adapters, 3, 3).unwrap();
+        assert_eq!(chain.count_arrangements(), Ok(274));
+        assert_eq!(chain.count_arrangements_mod(100), Ok(74));
+        assert_eq!(chain.count_arrangements_mod(1), Ok(0));
+        assert_eq!(chain.count_arrangements_mod(u128::MAX), Ok(274));
+        assert!(chain.count_arrangements_mod(0).is_err());
+    }
+
+    #[test]
+    fn test_xorshift_seeds() {
+        for seed in 0..100u64 {
+            let a: Vec<u64> = (0..4).scan(XorShift::new(seed), |rng, _| Some(rng.next())).collect();
+            let b: Vec<u64> = (0..4).scan(XorShift::new(seed + 1), |rng, _| Some(rng.next())).collect();
+            assert_ne!(a, b, "seeds {} and {}", seed, seed + 1);
+        }
+    }
+
+    #[test]
+    fn test_add_mod() {
+        assert_eq!(add_mod(3, 4, 5), 2);
+        assert_eq!(add_mod(0, 0, 1), 0);
+        assert_eq!(add_mod(u128::MAX - 1, u128::MAX - 1, u128::MAX), u128::MAX - 2);
+        assert_eq!(add_mod(1 << 127, 1 << 127, u128::MAX), 1);
+    }
+
+    #[test]
+    fn test_long_chain() {
+        let adapters: Vec<usize> = (1..=200_000).collect();
+        let chain = AdapterChain::new(&adapters, 3, 3).unwrap();
+        assert!(chain.count_arrangements_mod(1_000_000_007).unwrap() < 1_000_000_007);
+        assert!(chain.count_arrangements_mod(u128::MAX - 1).unwrap() < u128::MAX - 1);
     }
 
     #[test]
     fn test_examples() {
-        assert_eq!(find_paths_test(&mut vec![
+        assert_eq!(find_paths_test(&[
             16,
             10,
             15,
@@ -104,7 +446,7 @@ mod tests {
 
     #[test]
     fn test_example_two() {
-        assert_eq!(find_paths_test(&mut vec![
+        assert_eq!(find_paths_test(&[
             28,
             33,
             18,
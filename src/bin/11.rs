@@ -1,7 +1,15 @@
 extern crate aoc2020;
 
 use aoc2020::utils;
-use std::iter::{FromIterator};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
 struct SeatMap {
     width: i32,
@@ -20,6 +28,53 @@ const DIRECTIONS: [(i32, i32); 8] = [
     (1, 1)
 ];
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Neighbourhood {
+    // The eight seats immediately around
+    Adjacent,
+    // The first sight-blocking tile in each of the eight directions
+    LineOfSight,
+    // As LineOfSight, but only looking up to the given distance
+    LineOfSightMax(i32),
+}
+
+struct SeatRules {
+    neighbourhood: Neighbourhood,
+    // Occupied seats become empty when at least this many neighbours are occupied
+    threshold: usize,
+    // Tiles that stop a line of sight, the first one seen is the neighbour
+    blocks_sight: Vec<char>,
+}
+
+impl SeatRules {
+    fn part1() -> SeatRules {
+        SeatRules {
+            neighbourhood: Neighbourhood::Adjacent,
+            threshold: 4,
+            blocks_sight: vec!['L', '#'],
+        }
+    }
+
+    fn part2() -> SeatRules {
+        SeatRules {
+            neighbourhood: Neighbourhood::LineOfSight,
+            threshold: 5,
+            blocks_sight: vec!['L', '#'],
+        }
+    }
+}
+
+fn parse_neighbourhood(s: &str) -> Result<Neighbourhood, String> {
+    match s.split_once(':') {
+        None if s == "adjacent" => Ok(Neighbourhood::Adjacent),
+        None if s == "sight" => Ok(Neighbourhood::LineOfSight),
+        Some(("sight", max)) => max.parse::<i32>()
+            .map(Neighbourhood::LineOfSightMax)
+            .map_err(|_| format!("invalid sight distance '{}'", max)),
+        _ => Err(format!("unknown neighbourhood '{}'", s)),
+    }
+}
+
 impl SeatMap {
     fn get(&self, x: i32, y: i32) -> Option<char> {
         if x >= 0 && x < self.width && y >= 0 && y < self.height {
@@ -28,162 +83,534 @@ impl SeatMap {
             None
         }
     }
+}
 
-    fn get_surrounding(&self, x: i32, y: i32) -> Vec<char> {
-        DIRECTIONS.iter()
-            .map(|(delta_x, delta_y)| self.get(x + delta_x, y + delta_y))
-            .filter(Option::is_some)
-            .map(Option::unwrap)
-            .collect()
+// The simulation state, with everything that only depends on the layout worked out up front.
+// Each seat has a ray per direction holding the seats it might see along it, cut short at floor
+// that blocks sight, so finding a neighbour is a short walk over indices rather than a trace
+// across the map. When empty and occupied seats both block sight every ray is at most one seat
+// long, and when neither does the rays are empty.
+struct Simulation {
+    threshold: usize,
+    // Whether occupied and empty seats block sight. Floor that blocks sight never changes, so rays
+    // just end there and only ever hold seats.
+    occupied_stops: bool,
+    empty_stops: bool,
+    // Every ray is at most one seat long, so a seat's rays are just its neighbours
+    single_neighbour: bool,
+    // Rays for seat i, direction d are ray_tiles[ray_starts[i * 8 + d]..ray_starts[i * 8 + d + 1]]
+    ray_starts: Vec<usize>,
+    ray_tiles: Vec<usize>,
+    // Seats whose rays pass over each tile, which need looking at again when that tile changes
+    watchers: Vec<Vec<usize>>,
+    current: Vec<char>,
+    next: Vec<char>,
+    // Seats that might change this step, and a flag per tile for deduplicating them
+    frontier: Vec<usize>,
+    in_frontier: Vec<bool>,
+    changed: Vec<usize>,
+    // Zobrist hash of current, the XOR of tile_key over every occupied seat. Seats only ever flip,
+    // so each step just toggles the keys of the seats that changed.
+    hash: u64,
+}
+
+impl Simulation {
+    fn new(map: &SeatMap, rules: &SeatRules) -> Simulation {
+        let stops: HashSet<char> = match rules.neighbourhood {
+            Neighbourhood::Adjacent => map.data.iter().chain(['L', '#'].iter()).copied().collect(),
+            _ => rules.blocks_sight.iter().copied().collect(),
+        };
+
+        let max_distance = match rules.neighbourhood {
+            Neighbourhood::Adjacent => 1,
+            Neighbourhood::LineOfSight => i32::MAX,
+            Neighbourhood::LineOfSightMax(max) => max,
+        };
+
+        // Seats flip between empty and occupied, so unless both stop a ray the rest of the ray matters
+        let seats_always_stop = stops.contains(&'L') && stops.contains(&'#');
+        let seats_never_stop = !stops.contains(&'L') && !stops.contains(&'#');
+
+        let size = map.data.len();
+        let mut ray_starts: Vec<usize> = Vec::with_capacity(size * DIRECTIONS.len() + 1);
+        let mut ray_tiles: Vec<usize> = Vec::new();
+        let mut watchers: Vec<Vec<usize>> = vec![Vec::new(); size];
+        let mut frontier: Vec<usize> = Vec::new();
+
+        for (i, c) in map.data.iter().enumerate() {
+            let is_seat = *c == 'L' || *c == '#';
+            if is_seat {
+                frontier.push(i);
+            }
+
+            let x = (i % map.width as usize) as i32;
+            let y = (i / map.width as usize) as i32;
+
+            for (delta_x, delta_y) in DIRECTIONS.iter() {
+                ray_starts.push(ray_tiles.len());
+                if !is_seat {
+                    // Floor never changes, so never needs neighbours
+                    continue;
+                }
+
+                let mut distance = 1;
+                while distance <= max_distance {
+                    let tile_x = x + delta_x * distance;
+                    let tile_y = y + delta_y * distance;
+                    let tile = match map.get(tile_x, tile_y) {
+                        Some(tile) => tile,
+                        None => break,
+                    };
+                    let index = (tile_x + tile_y * map.width) as usize;
+                    let tile_is_seat = tile == 'L' || tile == '#';
+
+                    if tile_is_seat && !seats_never_stop {
+                        ray_tiles.push(index);
+                        watchers[index].push(i);
+                        if seats_always_stop {
+                            break;
+                        }
+                    } else if !tile_is_seat && stops.contains(&tile) {
+                        break;
+                    }
+
+                    distance += 1;
+                }
+            }
+        }
+        ray_starts.push(ray_tiles.len());
+
+        Simulation {
+            threshold: rules.threshold,
+            occupied_stops: stops.contains(&'#'),
+            empty_stops: stops.contains(&'L'),
+            single_neighbour: seats_always_stop,
+            ray_starts,
+            ray_tiles,
+            watchers,
+            current: map.data.clone(),
+            next: map.data.clone(),
+            in_frontier: map.data.iter().map(|c| *c == 'L' || *c == '#').collect(),
+            frontier,
+            changed: Vec::new(),
+            hash: zobrist_hash(&map.data),
+        }
+    }
+
+    fn ray(&self, i: usize, d: usize) -> &[usize] {
+        &self.ray_tiles[self.ray_starts[i * 8 + d]..self.ray_starts[i * 8 + d + 1]]
     }
 
-    fn get_visible_surrounding(&self, x: i32, y: i32) -> Vec<char> {
-        DIRECTIONS.iter()
-            .map(|(delta_x, delta_y)| -> Option<char> {
-                let mut i = 1;
-                loop {
-                    let c = self.get(x + (delta_x * i), y + (delta_y * i));
-                    if c.is_none() {
-                        return None;
-                    } else if c.unwrap() == 'L' || c.unwrap() == '#' {
-                        return c;
+    fn occupied_neighbours(&self, i: usize) -> usize {
+        if self.single_neighbour {
+            let neighbours = &self.ray_tiles[self.ray_starts[i * 8]..self.ray_starts[i * 8 + 8]];
+            return neighbours.iter().filter(|t| self.current[**t] == '#').count();
+        }
+
+        if !self.occupied_stops {
+            // Sight passes over occupied seats, so one is never what's seen
+            return 0;
+        }
+
+        (0..DIRECTIONS.len())
+            .filter(|d| {
+                for t in self.ray(i, *d) {
+                    match self.current[*t] {
+                        '#' => return true,
+                        'L' if self.empty_stops => return false,
+                        _ => {}
                     }
-                    i += 1;
                 }
+                false
             })
-            .filter(Option::is_some)
-            .map(Option::unwrap)
-            .collect()
+            .count()
     }
 
-    #[allow(dead_code)]
-    fn print(&self) {
-        println!("Printing map");
-        self.data
-            .chunks(self.width as usize)
-            .for_each(|s| println!("{}", s.iter().collect::<String>()));
-        println!("---");
-    }
-}
+    // Runs one generation over the frontier, returning whether anything changed
+    fn step(&mut self) -> bool {
+        self.changed.clear();
 
-fn gen_next_map_part1(seat_map: &SeatMap) -> Option<SeatMap> {
-    let next_map: Vec<char> = seat_map.data.iter().enumerate().map(|(i, c)| {
-        let x = i % seat_map.width as usize;
-        let y = i / seat_map.width as usize;
-        let surrounding = seat_map.get_surrounding(x as i32, y as i32);
+        for f in 0..self.frontier.len() {
+            let i = self.frontier[f];
+            self.in_frontier[i] = false;
 
-        if *c == 'L' {
-            // empty seats become occupied if none of the seats around are occupied
-            if !surrounding.iter().any(|s| *s == '#') {
-                '#'
-            } else {
-                'L'
+            let occupied = self.occupied_neighbours(i);
+            let c = self.current[i];
+            // empty seats become occupied if none of the seats around are occupied, and
+            // occupied seats become empty if enough of the seats around are occupied
+            if (c == 'L' && occupied == 0) || (c == '#' && occupied >= self.threshold) {
+                self.next[i] = if c == 'L' { '#' } else { 'L' };
+                self.changed.push(i);
             }
-        } else if *c == '#' {
-            // occupied seats become empty if 4 or more of the seats around are occupied
-            if surrounding.iter().filter(|s| **s == '#').count() >= 4 {
-                'L'
-            } else {
-                '#'
+        }
+
+        std::mem::swap(&mut self.current, &mut self.next);
+
+        // Bring the old buffer back in line, and queue up every seat that could see a change
+        self.frontier.clear();
+        for c in 0..self.changed.len() {
+            let i = self.changed[c];
+            self.next[i] = self.current[i];
+            self.hash ^= tile_key(i);
+
+            for w in std::iter::once(i).chain(self.watchers[i].iter().copied()) {
+                if !self.in_frontier[w] {
+                    self.in_frontier[w] = true;
+                    self.frontier.push(w);
+                }
             }
-        } else {
-            // floor spot
-            '.'
         }
-    }).collect();
 
-    if seat_map.data != next_map {
-        Some(SeatMap {
-            data: next_map,
-            width: seat_map.width,
-            height: seat_map.height,
-        })
-    } else {
-        None
+        !self.changed.is_empty()
+    }
+
+    fn occupied(&self) -> usize {
+        self.current.iter().filter(|c| **c == '#').count()
     }
 }
 
-fn run_part1(data: &Vec<String>) -> usize {
-    let width: i32 = data.first().unwrap().len() as i32;
-    let height: i32 = data.len() as i32;
+// One round of splitmix64 on the index, so every tile gets its own well mixed key
+fn tile_key(i: usize) -> u64 {
+    let mut z = (i as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
 
-    let mut map: SeatMap = SeatMap {
-        data: Vec::from_iter(data.iter().map(|s| s.chars()).flatten()),
-        width,
-        height,
-    };
+fn zobrist_hash(data: &[char]) -> u64 {
+    data.iter()
+        .enumerate()
+        .filter(|(_, c)| **c == '#')
+        .fold(0, |hash, (i, _)| hash ^ tile_key(i))
+}
+
+fn parse_map(data: &[String]) -> SeatMap {
+    SeatMap {
+        data: data.iter().flat_map(|s| s.chars()).collect(),
+        width: data.first().unwrap().len() as i32,
+        height: data.len() as i32,
+    }
+}
+
+const MAX_GENERATIONS: usize = 10_000;
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    // The map stopped changing at this generation
+    Stable { generation: usize, occupied: usize },
+    // The map at generation start + period is the same as at start, and will repeat forever
+    Cycle { start: usize, period: usize },
+    // Gave up after this many generations
+    Exhausted { generations: usize },
+}
+
+// Steps until the map settles, a previous state comes around again or max_generations is hit,
+// calling on_generation with each distinct state, starting with the initial layout. States are
+// remembered by their 64 bit Zobrist hash rather than a full copy, so big maps don't cost memory per
+// generation, and the hash is kept up to date as seats change rather than rebuilt from the whole map.
+// A matching hash is confirmed by replaying from the start, so a collision can't fake a cycle.
+fn simulate<F: FnMut(usize, &Simulation)>(map: &SeatMap, rules: &SeatRules, max_generations: usize, mut on_generation: F) -> Outcome {
+    let mut simulation = Simulation::new(map, rules);
+    let mut seen: HashMap<u64, usize> = HashMap::new();
 
+    let mut generation = 0;
     loop {
-        let next_map = gen_next_map_part1(&map);
-        if next_map.is_none() {
-            // Done! Count the seats
-            return map.data.iter().filter(|c| **c == '#').count();
+        if let Some(start) = seen.insert(simulation.hash, generation) {
+            if state_at(map, rules, start) == simulation.current {
+                return Outcome::Cycle { start, period: generation - start };
+            }
         }
+        on_generation(generation, &simulation);
 
-        map = next_map.unwrap();
-    };
+        if generation == max_generations {
+            return Outcome::Exhausted { generations: generation };
+        }
+
+        if !simulation.step() {
+            return Outcome::Stable { generation, occupied: simulation.occupied() };
+        }
+        generation += 1;
+    }
+}
+
+fn state_at(map: &SeatMap, rules: &SeatRules, generation: usize) -> Vec<char> {
+    let mut simulation = Simulation::new(map, rules);
+    for _ in 0..generation {
+        simulation.step();
+    }
+
+    simulation.current
+}
+
+fn run(data: &[String], rules: &SeatRules, max_generations: usize) -> Outcome {
+    simulate(&parse_map(data), rules, max_generations, |_, _| {})
+}
+
+struct Frame {
+    generation: usize,
+    occupied: usize,
+    data: Vec<char>,
 }
 
-fn gen_next_map_part2(seat_map: &SeatMap) -> Option<SeatMap> {
-    let next_map: Vec<char> = seat_map.data.iter().enumerate().map(|(i, c)| {
-        let x = i % seat_map.width as usize;
-        let y = i / seat_map.width as usize;
-        let surrounding = seat_map.get_visible_surrounding(x as i32, y as i32);
+// Every distinct generation from the initial layout, until the map stops changing or repeats
+fn record(map: &SeatMap, rules: &SeatRules, max_generations: usize) -> (Vec<Frame>, Outcome) {
+    let mut frames: Vec<Frame> = Vec::new();
+
+    let outcome = simulate(map, rules, max_generations, |generation, simulation| {
+        frames.push(Frame {
+            generation,
+            occupied: simulation.occupied(),
+            data: simulation.current.clone(),
+        });
+    });
+
+    (frames, outcome)
+}
 
-        if *c == 'L' {
-            // empty seats become occupied if none of the seats around are occupied
-            if !surrounding.iter().any(|s| *s == '#') {
-                '#'
-            } else {
-                'L'
+fn render_ansi(frame: &Frame, width: usize) -> String {
+    // Clear the screen and move the cursor home so each frame draws over the last
+    let mut output = format!("\x1b[2J\x1b[H Generation {} - occupied {}\n", frame.generation, frame.occupied);
+    for row in frame.data.chunks(width) {
+        for c in row {
+            match c {
+                '#' => output.push_str("\x1b[31m#\x1b[0m"),
+                'L' => output.push_str("\x1b[32mL\x1b[0m"),
+                c => output.push(*c),
             }
-        } else if *c == '#' {
-            // occupied seats become empty if 5 or more of the seats around are occupied
-            if surrounding.iter().filter(|s| **s == '#').count() >= 5 {
-                'L'
-            } else {
-                '#'
+        }
+        output.push('\n');
+    }
+
+    output
+}
+
+const PALETTE: [(u8, u8, u8); 8] = [
+    (0, 0, 0),
+    (48, 48, 48),
+    (40, 170, 70),
+    (220, 50, 50),
+    (255, 255, 255),
+    (0, 0, 0),
+    (0, 0, 0),
+    (0, 0, 0),
+];
+const BACKGROUND: u8 = 0;
+const FLOOR: u8 = 1;
+const EMPTY: u8 = 2;
+const OCCUPIED: u8 = 3;
+const TEXT: u8 = 4;
+
+const CELL_SIZE: usize = 4;
+const TEXT_SCALE: usize = 2;
+// Glyph height plus a row of padding above and below
+const HEADER_HEIGHT: usize = 7 * TEXT_SCALE;
+
+// 3x5 glyphs, each row is three bits with the leftmost pixel highest
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'G' => [0b111, 0b100, 0b101, 0b101, 0b111],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        _ => [0; 5],
+    }
+}
+
+// Palette indices for a frame, with the generation and occupied count written across the top
+fn render_image(frame: &Frame, width: usize) -> (usize, usize, Vec<u8>) {
+    let height = frame.data.len() / width;
+    let image_width = width * CELL_SIZE;
+    let image_height = HEADER_HEIGHT + height * CELL_SIZE;
+    let mut pixels = vec![BACKGROUND; image_width * image_height];
+
+    let label = format!("G{} #{}", frame.generation, frame.occupied);
+    for (n, c) in label.chars().enumerate() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+
+                for dy in 0..TEXT_SCALE {
+                    for dx in 0..TEXT_SCALE {
+                        let x = (1 + n * 4 + column) * TEXT_SCALE + dx;
+                        let y = (1 + row) * TEXT_SCALE + dy;
+                        if x < image_width {
+                            pixels[x + y * image_width] = TEXT;
+                        }
+                    }
+                }
             }
-        } else {
-            // floor spot
-            '.'
         }
-    }).collect();
+    }
 
-    if seat_map.data != next_map {
-        Some(SeatMap {
-            data: next_map,
-            width: seat_map.width,
-            height: seat_map.height,
-        })
-    } else {
-        None
+    for (i, c) in frame.data.iter().enumerate() {
+        let colour = match c {
+            '#' => OCCUPIED,
+            'L' => EMPTY,
+            _ => FLOOR,
+        };
+
+        let cell_x = (i % width) * CELL_SIZE;
+        let cell_y = HEADER_HEIGHT + (i / width) * CELL_SIZE;
+        // Leave a one pixel gap between cells
+        for dy in 0..CELL_SIZE - 1 {
+            for dx in 0..CELL_SIZE - 1 {
+                pixels[cell_x + dx + (cell_y + dy) * image_width] = colour;
+            }
+        }
     }
+
+    (image_width, image_height, pixels)
 }
 
-fn run_part2(data: &Vec<String>) -> usize {
-    let width: i32 = data.first().unwrap().len() as i32;
-    let height: i32 = data.len() as i32;
+fn encode_ppm(frame: &Frame, width: usize) -> Vec<u8> {
+    let (image_width, image_height, pixels) = render_image(frame, width);
+
+    let mut ppm = format!(
+        "P6\n# generation {} occupied {}\n{} {}\n255\n",
+        frame.generation, frame.occupied, image_width, image_height
+    ).into_bytes();
+    for p in pixels {
+        let (r, g, b) = PALETTE[p as usize];
+        ppm.extend_from_slice(&[r, g, b]);
+    }
+
+    ppm
+}
 
-    let mut map: SeatMap = SeatMap {
-        data: Vec::from_iter(data.iter().map(|s| s.chars()).flatten()),
-        width,
-        height,
+// A looping animated GIF with one image per frame, delay is in hundredths of a second
+fn encode_gif(frames: &[Frame], width: usize, delay: u16) -> Result<Vec<u8>, String> {
+    let images: Vec<(usize, usize, Vec<u8>)> = frames.iter().map(|f| render_image(f, width)).collect();
+    let (image_width, image_height) = match images.first() {
+        Some((w, h, _)) => (*w, *h),
+        None => return Err(String::from("no frames to encode")),
     };
+    if image_width > u16::MAX as usize || image_height > u16::MAX as usize {
+        return Err(format!(
+            "a {}x{} image is too large for a GIF, which allows at most {} pixels each way",
+            image_width,
+            image_height,
+            u16::MAX
+        ));
+    }
 
-    loop {
-        let next_map = gen_next_map_part2(&map);
-        if next_map.is_none() {
-            // Done! Count the seats
-            return map.data.iter().filter(|c| **c == '#').count();
+    let palette: Vec<u8> = PALETTE.iter().flat_map(|(r, g, b)| vec![*r, *g, *b]).collect();
+    let mut encoder = gif::Encoder::new(Vec::new(), image_width as u16, image_height as u16, &palette)
+        .map_err(|e| e.to_string())?;
+    encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+
+    for (frame, (_, _, pixels)) in frames.iter().zip(images.iter()) {
+        let comment = format!("generation {} occupied {}", frame.generation, frame.occupied);
+        encoder.write_raw_extension(gif::AnyExtension(0xFE), &[comment.as_bytes()]).map_err(|e| e.to_string())?;
+
+        let image = gif::Frame {
+            delay,
+            width: image_width as u16,
+            height: image_height as u16,
+            buffer: Cow::Borrowed(pixels),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&image).map_err(|e| e.to_string())?;
+    }
+
+    encoder.into_inner().map_err(|e| e.to_string())
+}
+
+fn animate(data: &[String], rules: &SeatRules, format: &str, delay_ms: u64, out_dir: &str) -> io::Result<()> {
+    let map = parse_map(data);
+    let width = map.width as usize;
+    let (frames, _) = record(&map, rules, MAX_GENERATIONS);
+
+    match format {
+        "ansi" => {
+            for frame in &frames {
+                print!("{}", render_ansi(frame, width));
+                io::stdout().flush()?;
+                thread::sleep(Duration::from_millis(delay_ms));
+            }
         }
+        "ppm" => {
+            fs::create_dir_all(out_dir)?;
+            for frame in &frames {
+                let path = Path::new(out_dir).join(format!("frame_{:04}.ppm", frame.generation));
+                fs::write(path, encode_ppm(frame, width))?;
+            }
+        }
+        "gif" => {
+            // GIF delays are in hundredths of a second and only 16 bits wide
+            let delay = u16::try_from(delay_ms / 10)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("delay {}ms is too long for a GIF", delay_ms)))?;
+            fs::create_dir_all(out_dir)?;
+            let gif = encode_gif(&frames, width, delay).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            fs::write(Path::new(out_dir).join("seating.gif"), gif)?;
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown format '{}'", format))),
+    }
 
-        map = next_map.unwrap();
-    };
+    Ok(())
+}
+
+fn occupied_when_stable(outcome: Outcome) -> usize {
+    match outcome {
+        Outcome::Stable { occupied, .. } => occupied,
+        outcome => panic!("seating never settles: {:?}", outcome),
+    }
+}
+
+fn run_part1(data: &[String]) -> usize {
+    occupied_when_stable(run(data, &SeatRules::part1(), MAX_GENERATIONS))
+}
+
+fn run_part2(data: &[String]) -> usize {
+    occupied_when_stable(run(data, &SeatRules::part2(), MAX_GENERATIONS))
 }
 
 fn main() {
     let data: Vec<String> = utils::read_lines("./input_data/11.txt");
+
+    // Custom rules start from part 2 and override whatever is given, e.g. --neighbourhood sight:3 --threshold 4
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 {
+        let mut rules = SeatRules::part2();
+        if let Some(neighbourhood) = utils::arg_value(&args, "--neighbourhood") {
+            rules.neighbourhood = parse_neighbourhood(neighbourhood).unwrap();
+        }
+        if let Some(threshold) = utils::arg_value(&args, "--threshold") {
+            rules.threshold = threshold.parse::<usize>().unwrap();
+        }
+        if let Some(blocks_sight) = utils::arg_value(&args, "--blocks-sight") {
+            rules.blocks_sight = blocks_sight.chars().collect();
+        }
+
+        // e.g. --animate gif --delay 200 --out frames
+        if let Some(format) = utils::arg_value(&args, "--animate") {
+            let delay_ms = utils::arg_value(&args, "--delay").map(|d| d.parse::<u64>().unwrap()).unwrap_or(100);
+            let out_dir = utils::arg_value(&args, "--out").unwrap_or(".");
+            animate(&data, &rules, format, delay_ms, out_dir).unwrap();
+            return;
+        }
+
+        let max_generations = utils::arg_value(&args, "--max-generations")
+            .map(|m| m.parse::<usize>().unwrap())
+            .unwrap_or(MAX_GENERATIONS);
+
+        match run(&data, &rules, max_generations) {
+            Outcome::Stable { generation, occupied } => println!("Stable after {} generations, occupied: {}", generation, occupied),
+            Outcome::Cycle { start, period } => println!("Cycle of period {} starting at generation {}", period, start),
+            Outcome::Exhausted { generations } => println!("No repeat within {} generations", generations),
+        }
+        return;
+    }
+
     println!("Part 1: {}", run_part1(&data));
     println!("Part 2: {}", run_part2(&data));
 }
@@ -205,7 +632,7 @@ mod tests {
             "LLLLLLLLLL",
             "L.LLLLLL.L",
             "L.LLLLL.LL"
-        ].iter().map(|s| s.to_string()).collect()), 37);
+        ].iter().map(|s| s.to_string()).collect::<Vec<String>>()), 37);
     }
 
     #[test]
@@ -221,6 +648,268 @@ mod tests {
             "LLLLLLLLLL",
             "L.LLLLLL.L",
             "L.LLLLL.LL"
-        ].iter().map(|s| s.to_string()).collect()), 26);
+        ].iter().map(|s| s.to_string()).collect::<Vec<String>>()), 26);
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        let data: Vec<String> = [
+            "L.LL.LL.LL",
+            "LLLLLLL.LL",
+            "L.L.L..L..",
+            "LLLL.LL.LL",
+            "L.LL.LL.LL",
+            "L.LLLLL.LL",
+            "..L.L.....",
+            "LLLLLLLLLL",
+            "L.LLLLLL.L",
+            "L.LLLLL.LL"
+        ].iter().map(|s| s.to_string()).collect();
+
+        // Line of sight limited to one tile is the same as only looking at adjacent seats
+        assert_eq!(run(&data, &SeatRules {
+            neighbourhood: Neighbourhood::LineOfSightMax(1),
+            threshold: 4,
+            blocks_sight: vec!['L', '#'],
+        }, MAX_GENERATIONS), Outcome::Stable { generation: 5, occupied: 37 });
+
+        assert_eq!(run(&data, &SeatRules {
+            neighbourhood: Neighbourhood::LineOfSightMax(100),
+            threshold: 5,
+            blocks_sight: vec!['L', '#'],
+        }, MAX_GENERATIONS), Outcome::Stable { generation: 6, occupied: 26 });
+    }
+
+    #[test]
+    fn test_cycle() {
+        let data: Vec<String> = vec![String::from("L.L")];
+
+        // Nothing ever stays occupied, so the seats flip every generation
+        let rules = SeatRules {
+            neighbourhood: Neighbourhood::Adjacent,
+            threshold: 0,
+            blocks_sight: vec!['L', '#'],
+        };
+        assert_eq!(run(&data, &rules, MAX_GENERATIONS), Outcome::Cycle { start: 0, period: 2 });
+        assert_eq!(run(&data, &rules, 1), Outcome::Exhausted { generations: 1 });
+
+        let (frames, outcome) = record(&parse_map(&data), &rules, MAX_GENERATIONS);
+        assert_eq!(outcome, Outcome::Cycle { start: 0, period: 2 });
+        assert_eq!(frames.iter().map(|f| f.occupied).collect::<Vec<usize>>(), vec![0, 2]);
+
+        let map = parse_map(&data);
+        assert_eq!(state_at(&map, &rules, 0), vec!['L', '.', 'L']);
+        assert_eq!(state_at(&map, &rules, 1), vec!['#', '.', '#']);
+        assert_eq!(state_at(&map, &rules, 2), state_at(&map, &rules, 0));
+    }
+
+    #[test]
+    fn test_parse_neighbourhood() {
+        assert_eq!(parse_neighbourhood("adjacent"), Ok(Neighbourhood::Adjacent));
+        assert_eq!(parse_neighbourhood("sight"), Ok(Neighbourhood::LineOfSight));
+        assert_eq!(parse_neighbourhood("sight:3"), Ok(Neighbourhood::LineOfSightMax(3)));
+        assert!(parse_neighbourhood("sight:x").is_err());
+        assert!(parse_neighbourhood("hex").is_err());
+    }
+
+    // Straightforward generation, tracing every ray from every seat
+    fn step_naive(map: &SeatMap, rules: &SeatRules) -> Vec<char> {
+        let max_distance = match rules.neighbourhood {
+            Neighbourhood::Adjacent => 1,
+            Neighbourhood::LineOfSight => i32::MAX,
+            Neighbourhood::LineOfSightMax(max) => max,
+        };
+
+        (0..map.data.len()).map(|i| {
+            let (x, y) = (i as i32 % map.width, i as i32 / map.width);
+            let occupied = DIRECTIONS.iter().filter(|(dx, dy)| {
+                let mut distance = 1;
+                while distance <= max_distance {
+                    match map.get(x + dx * distance, y + dy * distance) {
+                        None => return false,
+                        Some(c) if rules.neighbourhood == Neighbourhood::Adjacent
+                            || rules.blocks_sight.contains(&c) => return c == '#',
+                        _ => distance += 1,
+                    }
+                }
+                false
+            }).count();
+
+            match map.data[i] {
+                'L' if occupied == 0 => '#',
+                '#' if occupied >= rules.threshold => 'L',
+                c => c,
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn test_matches_naive() {
+        let data: Vec<String> = [
+            "L.LL.LL.LL",
+            "LLLLLLL.LL",
+            "L.L.L..L..",
+            "LLLL.LL.LL",
+            "L.LL.LL.LL",
+            "L.LLLLL.LL",
+            "..L.L.....",
+            "LLLLLLLLLL",
+            "L.LLLLLL.L",
+            "L.LLLLL.LL"
+        ].iter().map(|s| s.to_string()).collect();
+
+        for neighbourhood in &[Neighbourhood::Adjacent, Neighbourhood::LineOfSight, Neighbourhood::LineOfSightMax(2)] {
+            for blocks_sight in &["L#", "L#.", "#", "."] {
+                for threshold in 1..=6 {
+                    let rules = SeatRules {
+                        neighbourhood: *neighbourhood,
+                        threshold,
+                        blocks_sight: blocks_sight.chars().collect(),
+                    };
+                    let mut map = SeatMap { data: data.iter().flat_map(|s| s.chars()).collect(), width: 10, height: 10 };
+                    let mut simulation = Simulation::new(&map, &rules);
+
+                    for _ in 0..20 {
+                        simulation.step();
+                        map.data = step_naive(&map, &rules);
+                        assert_eq!(simulation.current, map.data, "{:?} {} {}", neighbourhood, blocks_sight, threshold);
+                        assert_eq!(simulation.hash, zobrist_hash(&map.data));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_ascii_tiles() {
+        let data: Vec<char> = "L█L#L.##L".chars().collect();
+        for neighbourhood in &[Neighbourhood::Adjacent, Neighbourhood::LineOfSight] {
+            let rules = SeatRules { neighbourhood: *neighbourhood, threshold: 2, blocks_sight: vec!['█', 'L', '#'] };
+            let mut map = SeatMap { data: data.clone(), width: 3, height: 3 };
+            let mut simulation = Simulation::new(&map, &rules);
+            for _ in 0..5 {
+                simulation.step();
+                map.data = step_naive(&map, &rules);
+                assert_eq!(simulation.current, map.data, "{:?}", neighbourhood);
+            }
+        }
+    }
+
+    fn example_frames() -> (SeatMap, Vec<Frame>) {
+        let map = parse_map(&[
+            "L.LL.LL.LL",
+            "LLLLLLL.LL",
+            "L.L.L..L..",
+            "LLLL.LL.LL",
+            "L.LL.LL.LL",
+            "L.LLLLL.LL",
+            "..L.L.....",
+            "LLLLLLLLLL",
+            "L.LLLLLL.L",
+            "L.LLLLL.LL"
+        ].iter().map(|s| s.to_string()).collect::<Vec<String>>());
+        let (frames, _) = record(&map, &SeatRules::part1(), MAX_GENERATIONS);
+
+        (map, frames)
+    }
+
+    #[test]
+    fn test_record() {
+        let (_, frames) = example_frames();
+        assert_eq!(frames.len(), 6);
+        assert_eq!(frames[0].occupied, 0);
+        assert_eq!(frames[1].occupied, 71);
+        assert_eq!(frames.last().unwrap().occupied, 37);
+        assert!(frames.iter().enumerate().all(|(i, f)| f.generation == i));
+    }
+
+    #[test]
+    fn test_render() {
+        let (_, frames) = example_frames();
+
+        let ansi = render_ansi(&frames[1], 10);
+        assert!(ansi.contains("Generation 1 - occupied 71"));
+
+        let (width, height, pixels) = render_image(&frames[1], 10);
+        assert_eq!((width, height), (40, 40 + HEADER_HEIGHT));
+        assert_eq!(pixels.iter().filter(|p| **p == OCCUPIED).count(), 71 * 9);
+        assert!(pixels.contains(&TEXT));
+
+        let ppm = encode_ppm(&frames[1], 10);
+        let header = format!("P6\n# generation 1 occupied 71\n40 {}\n255\n", height);
+        assert!(ppm.starts_with(header.as_bytes()));
+        assert_eq!(ppm.len(), header.len() + width * height * 3);
+    }
+
+    // Decode it again with the gif crate to check every frame and the delay made it through
+    #[test]
+    fn test_gif() {
+        let (_, frames) = example_frames();
+        let gif = encode_gif(&frames, 10, 50).unwrap();
+        assert!(gif.starts_with(b"GIF89a"));
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(&gif[..]).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (40, 40 + HEADER_HEIGHT as u16));
+
+        let mut decoded = 0;
+        while let Some(image) = decoder.read_next_frame().unwrap() {
+            assert_eq!(image.delay, 50);
+            assert_eq!(image.buffer.to_vec(), render_image(&frames[decoded], 10).2);
+            decoded += 1;
+        }
+        assert_eq!(decoded, frames.len());
+    }
+
+    #[test]
+    fn test_gif_delay_too_long() {
+        let data: Vec<String> = vec![String::from("L.L")];
+        let out_dir = env::temp_dir().join("aoc2020_11_never_created");
+        let err = animate(&data, &SeatRules::part1(), "gif", 655_360, out_dir.to_str().unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!out_dir.exists());
+    }
+
+    #[test]
+    fn test_gif_too_large() {
+        let frame = Frame { generation: 0, occupied: 0, data: vec!['.'; 20_000] };
+        assert!(encode_gif(&[frame], 20_000, 10).is_err());
+        assert!(encode_gif(&[], 10, 10).is_err());
+    }
+
+    // The seats seen from seat i, one per direction at most
+    fn neighbours(simulation: &Simulation, i: usize) -> Vec<char> {
+        (0..DIRECTIONS.len())
+            .filter_map(|d| {
+                simulation.ray(i, d)
+                    .iter()
+                    .map(|t| simulation.current[*t])
+                    .find(|c| (*c == '#' && simulation.occupied_stops) || (*c == 'L' && simulation.empty_stops))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_blocks_sight() {
+        let map = SeatMap {
+            data: "#.L.#".chars().collect(),
+            width: 5,
+            height: 1,
+        };
+
+        let mut rules = SeatRules::part2();
+        assert_eq!(neighbours(&Simulation::new(&map, &rules), 0), vec!['L']);
+
+        // Empty seats no longer block sight
+        rules.blocks_sight = vec!['#'];
+        assert_eq!(neighbours(&Simulation::new(&map, &rules), 0), vec!['#']);
+
+        rules.neighbourhood = Neighbourhood::LineOfSightMax(3);
+        assert_eq!(neighbours(&Simulation::new(&map, &rules), 0), Vec::<char>::new());
+
+        // Only floor next to it
+        rules.neighbourhood = Neighbourhood::Adjacent;
+        assert_eq!(neighbours(&Simulation::new(&map, &rules), 0), Vec::<char>::new());
     }
 }
\ No newline at end of file
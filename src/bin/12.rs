@@ -1,96 +1,266 @@
 extern crate aoc2020;
 
 use aoc2020::utils;
+use std::env;
 
-fn parse(line: &String) -> (char, usize) {
-    (
-        line.chars().nth(0).unwrap(),
-        line.chars().skip(1).collect::<String>().parse::<usize>().unwrap()
-    )
-}
-
-fn rotate_deg(point: (i32, i32), center: (i32, i32), clockwise_degress: i32) -> (i32, i32) {
-    let rads = (clockwise_degress as f32).to_radians();
-    (
-        center.0 + (rads.cos() as i32 * (point.0 - center.0) + rads.sin() as i32 * (point.1 - center.1)),
-        center.1 + (-rads.sin() as i32 * (point.0 - center.0) + rads.cos() as i32 * (point.1 - center.1))
-    )
-}
-
-fn execute_instructions_part1(data: &Vec<String>) -> usize {
-    let mut position: (i32, i32) = (0, 0);
-    let mut facing: (i32, i32) = (1, 0);
-
-    // Action N means to move north by the given value.
-    // Action S means to move south by the given value.
-    // Action E means to move east by the given value.
-    // Action W means to move west by the given value.
-    // Action L means to turn left the given number of degrees.
-    // Action R means to turn right the given number of degrees.
-    // Action F means to move forward by the given value in the direction the ship is currently facing.
-
-    data.iter().map(|l| parse(l)).for_each(|(op, value)| {
-        match op {
-            'N' => position.1 += value as i32,
-            'S' => position.1 -= value as i32,
-            'E' => position.0 += value as i32,
-            'W' => position.0 -= value as i32,
-            'L' => facing = rotate_deg(facing, (0, 0), -(value as i32)),
-            'R' => facing = rotate_deg(facing, (0, 0), value as i32),
-            'F' => {
-                position.0 += facing.0 * value as i32;
-                position.1 += facing.1 * value as i32;
-            }
-            _ => panic!()
-        }
-    });
-
-    (position.0.abs() + position.1.abs()) as usize
-}
-
-fn execute_instructions_part2(data: &Vec<String>) -> usize {
-    let mut position: (i32, i32) = (0, 0);
-    let mut waypoint: (i32, i32) = (10, 1);
-
-    // Action N means to move north by the given value.
-    // Action S means to move south by the given value.
-    // Action E means to move east by the given value.
-    // Action W means to move west by the given value.
-    // Action L means to turn left the given number of degrees.
-    // Action R means to turn right the given number of degrees.
-    // Action F means to move forward by the given value in the direction the ship is currently facing.
-
-    data.iter().map(|l| parse(l)).for_each(|(op, value)| {
-        match op {
-            'N' => waypoint.1 += value as i32,
-            'S' => waypoint.1 -= value as i32,
-            'E' => waypoint.0 += value as i32,
-            'W' => waypoint.0 -= value as i32,
-            'L' => waypoint = rotate_deg(waypoint, position, -(value as i32)),
-            'R' => waypoint = rotate_deg(waypoint, position, value as i32),
-            'F' => {
-                let movement = (
-                    (waypoint.0 - position.0) * value as i32,
-                    (waypoint.1 - position.1) * value as i32
-                );
-
-                position.0 += movement.0;
-                position.1 += movement.1;
-
-                waypoint.0 += movement.0;
-                waypoint.1 += movement.1;
-            }
-            _ => panic!()
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Vector {
+    // East is positive
+    x: i32,
+    // North is positive
+    y: i32,
+}
+
+impl Vector {
+    fn new(x: i32, y: i32) -> Vector {
+        Vector { x, y }
+    }
+
+    fn add(self, other: Vector) -> Vector {
+        Vector::new(self.x + other.x, self.y + other.y)
+    }
+
+    fn scale(self, factor: i32) -> Vector {
+        Vector::new(self.x * factor, self.y * factor)
+    }
+
+    fn manhattan_distance(self) -> usize {
+        (self.x.abs() + self.y.abs()) as usize
+    }
+}
+
+// Only right angles keep a grid position on the grid, so anything else is an error
+fn rotate_deg(point: Vector, clockwise_degrees: i32) -> Result<Vector, String> {
+    if clockwise_degrees % 90 != 0 {
+        return Err(format!("can only turn by multiples of 90 degrees, not {}", clockwise_degrees));
+    }
+
+    Ok(match clockwise_degrees.rem_euclid(360) / 90 {
+        0 => point,
+        1 => Vector::new(point.y, -point.x),
+        2 => Vector::new(-point.x, -point.y),
+        _ => Vector::new(-point.y, point.x),
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Ship {
+    position: Vector,
+    // Which way the ship moves on F, the facing in part 1 and the waypoint offset in part 2
+    heading: Vector,
+}
+
+impl Ship {
+    fn new(heading: Vector) -> Ship {
+        Ship {
+            position: Vector::new(0, 0),
+            heading,
         }
-    });
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum InstructionSet {
+    // N, S, E, W, L, R and F from the puzzle
+    V1,
+    // Adds B, T and U
+    V2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NavInstruction {
+    // N, S, E and W, as a unit direction and a distance
+    Move(Vector, i32),
+    // L and R, as clockwise degrees
+    Turn(i32),
+    Forward(i32),
+    Backward(i32),
+    // Jump onto the waypoint, which keeps its offset from the ship
+    Teleport,
+    UTurn,
+}
+
+fn parse_instruction(line: &str, instruction_set: InstructionSet) -> Result<NavInstruction, String> {
+    let mut chars = line.chars();
+    let action = chars.next().ok_or_else(|| String::from("empty instruction"))?;
+    let argument = chars.as_str();
+
+    let value = || argument.parse::<i32>()
+        .map_err(|_| format!("invalid value '{}' in '{}'", argument, line));
+
+    let instruction = match action {
+        'N' => NavInstruction::Move(Vector::new(0, 1), value()?),
+        'S' => NavInstruction::Move(Vector::new(0, -1), value()?),
+        'E' => NavInstruction::Move(Vector::new(1, 0), value()?),
+        'W' => NavInstruction::Move(Vector::new(-1, 0), value()?),
+        'L' => NavInstruction::Turn(-value()?),
+        'R' => NavInstruction::Turn(value()?),
+        'F' => NavInstruction::Forward(value()?),
+        'B' => NavInstruction::Backward(value()?),
+        'T' | 'U' if !argument.is_empty() => return Err(format!("'{}' doesn't take a value", line)),
+        'T' => NavInstruction::Teleport,
+        'U' => NavInstruction::UTurn,
+        _ => return Err(format!("unknown action '{}' in '{}'", action, line)),
+    };
+
+    let extended = matches!(instruction, NavInstruction::Backward(_) | NavInstruction::Teleport | NavInstruction::UTurn);
+    if extended && instruction_set == InstructionSet::V1 {
+        return Err(format!("action '{}' needs instruction set v2", action));
+    }
+
+    Ok(instruction)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NavMode {
+    // Compass moves move the ship, the heading is the way it faces
+    Direct,
+    // Compass moves move the waypoint, the heading is its offset from the ship
+    Waypoint,
+}
+
+fn execute(ship: Ship, instruction: NavInstruction, mode: NavMode) -> Result<Ship, String> {
+    let mut ship = ship;
+    match instruction {
+        NavInstruction::Move(direction, value) => match mode {
+            NavMode::Direct => ship.position = ship.position.add(direction.scale(value)),
+            NavMode::Waypoint => ship.heading = ship.heading.add(direction.scale(value)),
+        },
+        NavInstruction::Turn(degrees) => ship.heading = rotate_deg(ship.heading, degrees)?,
+        NavInstruction::Forward(value) => ship.position = ship.position.add(ship.heading.scale(value)),
+        NavInstruction::Backward(value) => ship.position = ship.position.add(ship.heading.scale(-value)),
+        NavInstruction::Teleport => match mode {
+            NavMode::Direct => return Err(String::from("can't teleport without a waypoint")),
+            NavMode::Waypoint => ship.position = ship.position.add(ship.heading),
+        },
+        NavInstruction::UTurn => ship.heading = ship.heading.scale(-1),
+    }
+
+    Ok(ship)
+}
+
+#[derive(Debug, PartialEq)]
+struct TraceStep {
+    // 0 is the starting state, before any instruction
+    step: usize,
+    instruction: String,
+    ship: Ship,
+}
+
+fn navigate(data: &[String], mode: NavMode, instruction_set: InstructionSet) -> Result<Vec<TraceStep>, String> {
+    let mut ship = match mode {
+        NavMode::Direct => Ship::new(Vector::new(1, 0)),
+        NavMode::Waypoint => Ship::new(Vector::new(10, 1)),
+    };
+    let mut trace = vec![TraceStep { step: 0, instruction: String::new(), ship }];
+
+    for (step, line) in data.iter().enumerate() {
+        ship = parse_instruction(line, instruction_set)
+            .and_then(|instruction| execute(ship, instruction, mode))
+            .map_err(|e| format!("line {}: {}", step + 1, e))?;
+
+        trace.push(TraceStep { step: step + 1, instruction: line.clone(), ship });
+    }
+
+    Ok(trace)
+}
 
-    (position.0.abs() + position.1.abs()) as usize
+fn execute_instructions_part1(data: &[String], instruction_set: InstructionSet) -> Result<usize, String> {
+    Ok(navigate(data, NavMode::Direct, instruction_set)?.last().unwrap().ship.position.manhattan_distance())
+}
+
+fn execute_instructions_part2(data: &[String], instruction_set: InstructionSet) -> Result<usize, String> {
+    Ok(navigate(data, NavMode::Waypoint, instruction_set)?.last().unwrap().ship.position.manhattan_distance())
+}
+
+fn trace_to_csv(trace: &[TraceStep]) -> String {
+    let mut csv = String::from("step,instruction,x,y,heading_x,heading_y\n");
+    for t in trace {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            t.step, t.instruction, t.ship.position.x, t.ship.position.y, t.ship.heading.x, t.ship.heading.y
+        ));
+    }
+
+    csv
+}
+
+// The ship's path as a solid line, with a dot per step that names the instruction when hovered.
+// In waypoint mode the waypoint's absolute position is drawn as a dashed trail too.
+fn trace_to_svg(trace: &[TraceStep], waypoint: bool) -> String {
+    let positions: Vec<Vector> = trace.iter().map(|t| t.ship.position).collect();
+    let waypoints: Vec<Vector> = trace.iter().map(|t| t.ship.position.add(t.ship.heading)).collect();
+
+    let mut points: Vec<Vector> = positions.clone();
+    if waypoint {
+        points.extend_from_slice(&waypoints);
+    }
+
+    let min_x = points.iter().map(|p| p.x).min().unwrap();
+    let max_x = points.iter().map(|p| p.x).max().unwrap();
+    let min_y = points.iter().map(|p| p.y).min().unwrap();
+    let max_y = points.iter().map(|p| p.y).max().unwrap();
+    let size = (max_x - min_x).max(max_y - min_y).max(1);
+    let margin = (size / 20).max(1);
+
+    // SVG's y axis points down, so flip it to keep north at the top
+    let polyline = |vectors: &[Vector]| -> String {
+        vectors.iter().map(|v| format!("{},{}", v.x, -v.y)).collect::<Vec<String>>().join(" ")
+    };
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+        min_x - margin, -max_y - margin, max_x - min_x + 2 * margin, max_y - min_y + 2 * margin
+    );
+
+    if waypoint {
+        svg.push_str(&format!(
+            "  <polyline points=\"{}\" fill=\"none\" stroke=\"orange\" stroke-dasharray=\"4 2\" vector-effect=\"non-scaling-stroke\"/>\n",
+            polyline(&waypoints)
+        ));
+    }
+
+    svg.push_str(&format!(
+        "  <polyline points=\"{}\" fill=\"none\" stroke=\"steelblue\" stroke-width=\"2\" vector-effect=\"non-scaling-stroke\"/>\n",
+        polyline(&positions)
+    ));
+
+    let radius = size as f64 / 300.0;
+    for t in trace {
+        svg.push_str(&format!(
+            "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"><title>{}: {} ({}, {})</title></circle>\n",
+            t.ship.position.x, -t.ship.position.y, radius,
+            if t.step == 0 { "green" } else if t.step == trace.len() - 1 { "red" } else { "steelblue" },
+            t.step, t.instruction, t.ship.position.x, t.ship.position.y
+        ));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
 }
 
 fn main() {
     let data: Vec<String> = utils::read_lines("./input_data/12.txt");
-    println!("Part 1: {}", execute_instructions_part1(&data));
-    println!("Part 2: {}", execute_instructions_part2(&data));
+
+    // e.g. "csv 2" or "svg 1", written to stdout, --v2 allows the extended instructions
+    let args: Vec<String> = env::args().filter(|a| a != "--v2").collect();
+    let instruction_set = if env::args().any(|a| a == "--v2") { InstructionSet::V2 } else { InstructionSet::V1 };
+
+    if let Some(format) = args.get(1) {
+        let part = args.get(2).map(String::as_str).unwrap_or("1");
+        let mode = if part == "2" { NavMode::Waypoint } else { NavMode::Direct };
+        let trace = navigate(&data, mode, instruction_set).unwrap();
+
+        match format.as_str() {
+            "csv" => print!("{}", trace_to_csv(&trace)),
+            "svg" => print!("{}", trace_to_svg(&trace, part == "2")),
+            _ => eprintln!("unknown format '{}'", format),
+        }
+        return;
+    }
+
+    println!("Part 1: {}", execute_instructions_part1(&data, instruction_set).unwrap());
+    println!("Part 2: {}", execute_instructions_part2(&data, instruction_set).unwrap());
 }
 
 #[cfg(test)]
@@ -99,20 +269,49 @@ mod tests {
 
     #[test]
     fn test_parse() {
-        let result = parse(&String::from("F10"));
-        assert_eq!(result.0, 'F');
-        assert_eq!(result.1, 10);
+        assert_eq!(parse_instruction("F10", InstructionSet::V1), Ok(NavInstruction::Forward(10)));
+        assert_eq!(parse_instruction("L90", InstructionSet::V1), Ok(NavInstruction::Turn(-90)));
+        assert_eq!(parse_instruction("S3", InstructionSet::V1), Ok(NavInstruction::Move(Vector::new(0, -1), 3)));
+        assert_eq!(parse_instruction("B4", InstructionSet::V2), Ok(NavInstruction::Backward(4)));
+        assert_eq!(parse_instruction("T", InstructionSet::V2), Ok(NavInstruction::Teleport));
+        assert_eq!(parse_instruction("U", InstructionSet::V2), Ok(NavInstruction::UTurn));
+
+        assert!(parse_instruction("X10", InstructionSet::V2).is_err());
+        assert!(parse_instruction("F", InstructionSet::V1).is_err());
+        assert!(parse_instruction("Fx", InstructionSet::V1).is_err());
+        assert!(parse_instruction("", InstructionSet::V1).is_err());
+        assert!(parse_instruction("U2", InstructionSet::V2).is_err());
+        assert!(parse_instruction("B4", InstructionSet::V1).is_err());
+        assert!(parse_instruction("T", InstructionSet::V1).is_err());
+    }
+
+    #[test]
+    fn test_extended_instructions() {
+        let data: Vec<String> = ["F10", "U", "B5", "N3", "T"].iter().map(|s| s.to_string()).collect();
+
+        // Facing west after the U-turn, so backing up goes east
+        let trace = navigate(&data[0..4], NavMode::Direct, InstructionSet::V2).unwrap();
+        assert_eq!(trace.last().unwrap().ship, Ship { position: Vector::new(15, 3), heading: Vector::new(-1, 0) });
+        assert!(navigate(&data, NavMode::Direct, InstructionSet::V2).is_err());
+
+        // Waypoint starts at (10, 1), flipped to (-10, -1), then moved to (-10, 2)
+        let trace = navigate(&data, NavMode::Waypoint, InstructionSet::V2).unwrap();
+        assert_eq!(trace[3].ship.position, Vector::new(150, 15));
+        assert_eq!(trace.last().unwrap().ship, Ship { position: Vector::new(140, 17), heading: Vector::new(-10, 2) });
+
+        let error = navigate(&data, NavMode::Waypoint, InstructionSet::V1).unwrap_err();
+        assert!(error.starts_with("line 2:"));
     }
 
     #[test]
     fn test_part1() {
-        assert_eq!(execute_instructions_part1(&vec![
+        assert_eq!(execute_instructions_part1(&[
             "F10",
             "N3",
             "F7",
             "R90",
             "F11"
-        ].iter().map(|s| s.to_string()).collect()), 25);
+        ].iter().map(|s| s.to_string()).collect::<Vec<String>>(), InstructionSet::V1), Ok(25));
     }
 
     #[test]
@@ -123,20 +322,70 @@ mod tests {
         // R90 rotates the waypoint around the ship clockwise 90 degrees, moving it to 4 units east and 10 units south of the ship. The ship remains at east 170, north 38.
         // F11 moves the ship to the waypoint 11 times (a total of 44 units east and 110 units south), leaving the ship at east 214, south 72. The waypoint stays 4 units east and 10 units south of the ship.
 
-        assert_eq!(execute_instructions_part2(&vec![
+        assert_eq!(execute_instructions_part2(&[
             "F10",
             "N3",
             "F7",
             "R90",
             "F11"
-        ].iter().map(|s| s.to_string()).collect()), 286);
+        ].iter().map(|s| s.to_string()).collect::<Vec<String>>(), InstructionSet::V1), Ok(286));
     }
 
     #[test]
     fn test_rotate() {
-        assert_eq!(rotate_deg((1, 0), (0, 0), -90), (0, 1));
-        assert_eq!(rotate_deg((1, 0), (0, 0), 90), (0, -1));
-        assert_eq!(rotate_deg((1, 0), (0, 0), -450), (0, 1));
-        assert_eq!(rotate_deg((1, 0), (0, 0), 450), (0, -1));
+        assert_eq!(rotate_deg(Vector::new(1, 0), -90), Ok(Vector::new(0, 1)));
+        assert_eq!(rotate_deg(Vector::new(1, 0), 90), Ok(Vector::new(0, -1)));
+        assert_eq!(rotate_deg(Vector::new(1, 0), -450), Ok(Vector::new(0, 1)));
+        assert_eq!(rotate_deg(Vector::new(1, 0), 450), Ok(Vector::new(0, -1)));
+        assert_eq!(rotate_deg(Vector::new(10, 4), 180), Ok(Vector::new(-10, -4)));
+        assert_eq!(rotate_deg(Vector::new(10, 4), 270), Ok(Vector::new(-4, 10)));
+        assert_eq!(rotate_deg(Vector::new(10, 4), 0), Ok(Vector::new(10, 4)));
+        assert!(rotate_deg(Vector::new(1, 0), 45).is_err());
+        assert!(rotate_deg(Vector::new(1, 0), -30).is_err());
+    }
+
+    #[test]
+    fn test_trace() {
+        let data: Vec<String> = ["F10", "N3", "F7", "R90", "F11"].iter().map(|s| s.to_string()).collect();
+
+        let trace = navigate(&data, NavMode::Waypoint, InstructionSet::V1).unwrap();
+        assert_eq!(trace.len(), 6);
+        assert_eq!(trace[0], TraceStep {
+            step: 0,
+            instruction: String::new(),
+            ship: Ship { position: Vector::new(0, 0), heading: Vector::new(10, 1) },
+        });
+        assert_eq!(trace[3].ship, Ship { position: Vector::new(170, 38), heading: Vector::new(10, 4) });
+        assert_eq!(trace[4].ship.heading, Vector::new(4, -10));
+        assert_eq!(trace[5].ship.position, Vector::new(214, -72));
+
+        let csv = trace_to_csv(&trace);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "step,instruction,x,y,heading_x,heading_y");
+        assert_eq!(lines[1], "0,,0,0,10,1");
+        assert_eq!(lines[4], "3,F7,170,38,10,4");
+        assert_eq!(lines.len(), 7);
+    }
+
+    #[test]
+    fn test_svg() {
+        let data: Vec<String> = ["F10", "N3", "F7", "R90", "F11"].iter().map(|s| s.to_string()).collect();
+
+        let svg = trace_to_svg(&navigate(&data, NavMode::Direct, InstructionSet::V1).unwrap(), false);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("points=\"0,0 10,0 10,-3 17,-3 17,-3 17,8\""));
+        assert!(!svg.contains("stroke-dasharray"));
+        assert_eq!(svg.matches("<circle").count(), 6);
+
+        let svg = trace_to_svg(&navigate(&data, NavMode::Waypoint, InstructionSet::V1).unwrap(), true);
+        assert!(svg.contains("stroke-dasharray"));
+        assert!(svg.contains("<title>3: F7 (170, 38)</title>"));
+    }
+
+    #[test]
+    fn test_non_right_angle() {
+        let data: Vec<String> = ["F10", "R45", "F1"].iter().map(|s| s.to_string()).collect();
+        assert!(execute_instructions_part1(&data, InstructionSet::V1).is_err());
+        assert!(execute_instructions_part2(&data, InstructionSet::V1).is_err());
     }
 }
\ No newline at end of file
@@ -1,12 +1,42 @@
 extern crate aoc2020;
 
-use aoc2020::utils;
+use aoc2020::{math, utils};
 use itertools::Itertools;
+use std::env;
+
+// A bus leaves every bus_id minutes, so 0 would never leave and divides by zero everywhere
+fn parse_bus_id(b: &str) -> Result<usize, String> {
+    match b.parse::<usize>() {
+        Ok(0) => Err(String::from("bus ID 0 never departs")),
+        Ok(bus_id) => Ok(bus_id),
+        Err(e) => Err(format!("bad bus ID '{}': {}", b, e)),
+    }
+}
+
+fn parse(data: &[String]) -> Result<(usize, Vec<Option<usize>>), String> {
+    if data.len() < 2 {
+        return Err(String::from("expected a departure time and a bus list"));
+    }
+
+    let departure_time = data[0].parse::<usize>().map_err(|e| format!("line 1: bad departure time '{}': {}", data[0], e))?;
+    let bus_id_list: Vec<Option<usize>> = data[1]
+        .split(',')
+        .map(|b| {
+            if b == "x" {
+                Ok(None)
+            } else {
+                parse_bus_id(b).map(Some).map_err(|e| format!("line 2: {}", e))
+            }
+        })
+        .collect::<Result<_, _>>()?;
 
-fn part1(departure_time: usize, raw_bus_id_list: &Vec<Option<usize>>) -> usize {
+    Ok((departure_time, bus_id_list))
+}
+
+fn part1(departure_time: usize, raw_bus_id_list: &[Option<usize>]) -> usize {
     let bus_id_list: Vec<usize> = raw_bus_id_list.iter()
-        .filter(|b| b.is_some())
-        .map(|b| b.unwrap())
+        .flatten()
+        .copied()
         .collect();
     let missed_by_times: Vec<usize> = bus_id_list.iter().map(|b| departure_time % *b).collect();
     let wait_times: Vec<usize> = bus_id_list.iter().zip(missed_by_times).map(|(b, m)| b - m).collect();
@@ -16,46 +46,87 @@ fn part1(departure_time: usize, raw_bus_id_list: &Vec<Option<usize>>) -> usize {
     bus_id_list[min_index] * wait_times[min_index]
 }
 
-fn part2(departure_time: usize, raw_bus_id_list: &Vec<Option<usize>>) -> usize {
-    let largest_number_index = raw_bus_id_list.iter().position_max().unwrap();
-    let largest_number = raw_bus_id_list[largest_number_index].unwrap();
+// The earliest timestamp where bus i leaves i minutes after it, so t = -i (mod bus_id)
+fn part2(raw_bus_id_list: &[Option<usize>]) -> Option<u128> {
+    let congruences: Vec<(i128, i128)> = raw_bus_id_list.iter()
+        .enumerate()
+        .filter_map(|(i, b)| b.map(|b| (-(i as i128), b as i128)))
+        .collect();
+
+    math::crt(&congruences).map(|(t, _)| t as u128)
+}
+
+// The first time at or after time that the bus leaves
+fn next_departure(bus_id: usize, time: usize) -> usize {
+    time.div_ceil(bus_id) * bus_id
+}
 
-    let mut multiple = (departure_time / largest_number) + 1;
-    loop {
-        let start = multiple * largest_number - largest_number_index;
-        let result = raw_bus_id_list.iter().enumerate().all(|(i, b)| {
-            if b.is_none() {
-                true
-            } else {
-                (start + i) % b.unwrap() == 0
-            }
-        });
+// Every departure of every bus between from and to inclusive, by bus
+fn departures(raw_bus_id_list: &[Option<usize>], from: usize, to: usize) -> Vec<(usize, Vec<usize>)> {
+    raw_bus_id_list.iter()
+        .flatten()
+        .map(|b| (*b, (next_departure(*b, from)..=to).step_by(*b).collect()))
+        .collect()
+}
 
-        if result {
-            return start;
-        }
+// (bus, departure) for each bus leaving within minutes of time, soonest first
+fn departing_within(raw_bus_id_list: &[Option<usize>], time: usize, minutes: usize) -> Vec<(usize, usize)> {
+    raw_bus_id_list.iter()
+        .flatten()
+        .map(|b| (*b, next_departure(*b, time)))
+        .filter(|(_, departure)| departure - time <= minutes)
+        .sorted_by_key(|(b, departure)| (*departure, *b))
+        .collect()
+}
 
-        multiple += 1;
-    }
+// The first time at or after time that all of the given buses leave at once
+fn next_together(bus_ids: &[usize], time: u128) -> u128 {
+    let period = bus_ids.iter().fold(1u128, |period, b| math::lcm(period, *b as u128));
+    time.div_ceil(period) * period
 }
 
 fn main() {
     let data: Vec<String> = utils::read_lines("./input_data/13.txt");
-
-    let departure_time = data[0].parse::<usize>().unwrap();
-    let bus_id_list: Vec<Option<usize>> = data[1]
-        .split(",")
-        .map(|b| {
-            if b == "x" {
-                None
-            } else {
-                Some(b.parse::<usize>().unwrap())
+    let (departure_time, bus_id_list) = match parse(&data) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    // e.g. "window 100 200", "within 10" or "together 7,13"
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("window") => {
+            let from = args[2].parse::<usize>().unwrap();
+            let to = args[3].parse::<usize>().unwrap();
+            for (bus, times) in departures(&bus_id_list, from, to) {
+                println!("{}: {}", bus, times.iter().join(", "));
             }
-        })
-        .collect();
+            return;
+        }
+        Some("within") => {
+            for (bus, departure) in departing_within(&bus_id_list, departure_time, args[2].parse::<usize>().unwrap()) {
+                println!("{} at {} (wait {})", bus, departure, departure - departure_time);
+            }
+            return;
+        }
+        Some("together") => {
+            match args[2].split(',').map(parse_bus_id).collect::<Result<Vec<usize>, String>>() {
+                Ok(bus_ids) => println!("{}", next_together(&bus_ids, departure_time as u128)),
+                Err(e) => eprintln!("{}", e),
+            }
+            return;
+        }
+        _ => {}
+    }
 
     println!("Part 1: {}", part1(departure_time, &bus_id_list));
-    println!("Part 2: {}", part2(departure_time, &bus_id_list));
+    match part2(&bus_id_list) {
+        Some(t) => println!("Part 2: {}", t),
+        None => println!("Part 2: no timestamp works for every bus"),
+    }
 }
 
 
@@ -66,25 +137,80 @@ mod tests {
     #[test]
     fn test_part2_simple() {
         let bus_list = vec![Some(2),Some(3)];
-        assert_eq!(part2(0, &bus_list), 2)
+        assert_eq!(part2(&bus_list), Some(2))
     }
 
 
     #[test]
     fn test_part2_simple2() {
         let bus_list = vec![Some(4),Some(3)];
-        assert_eq!(part2(0, &bus_list), 8)
+        assert_eq!(part2(&bus_list), Some(8))
     }
 
     #[test]
     fn test_part2_ex1() {
         let bus_list = vec![Some(17),None,Some(13),Some(19)];
-        assert_eq!(part2(0, &bus_list), 3417)
+        assert_eq!(part2(&bus_list), Some(3417))
     }
 
     #[test]
     fn test_part2_ex4() {
         let bus_list = vec![Some(67),Some(7), None,Some(59),Some(61)];
-        assert_eq!(part2(0, &bus_list), 1261476)
+        assert_eq!(part2(&bus_list), Some(1261476))
+    }
+
+    #[test]
+    fn test_part2_examples() {
+        assert_eq!(part2(&[Some(7), Some(13), None, None, Some(59), None, Some(31), Some(19)]), Some(1068781));
+        assert_eq!(part2(&[Some(67), None, Some(7), Some(59), Some(61)]), Some(779210));
+        assert_eq!(part2(&[Some(1789), Some(37), Some(47), Some(1889)]), Some(1202161486));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_part2_not_coprime() {
+        // Would need t to be both even and odd
+        assert_eq!(part2(&[Some(4), Some(6)]), None);
+        assert_eq!(part2(&[Some(4), None, Some(6)]), Some(4));
+        assert_eq!(part2(&[Some(6), Some(10), Some(15)]), None);
+        assert_eq!(part2(&[None, None]), Some(0));
+    }
+
+    #[test]
+    fn test_parse() {
+        let data: Vec<String> = vec![String::from("939"), String::from("7,13,x,x,59,x,31,19")];
+        let (departure_time, bus_id_list) = parse(&data).unwrap();
+        assert_eq!(departure_time, 939);
+        assert_eq!(bus_id_list, vec![Some(7), Some(13), None, None, Some(59), None, Some(31), Some(19)]);
+        assert_eq!(part1(departure_time, &bus_id_list), 295);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let parse_lines = |lines: &[&str]| parse(&lines.iter().map(|l| l.to_string()).collect::<Vec<String>>());
+        assert_eq!(parse_lines(&["939", "7,0,x"]), Err(String::from("line 2: bus ID 0 never departs")));
+        assert!(parse_lines(&["939", "7,y"]).unwrap_err().starts_with("line 2: bad bus ID 'y'"));
+        assert!(parse_lines(&["soon", "7"]).unwrap_err().starts_with("line 1:"));
+        assert!(parse_lines(&["939"]).is_err());
+    }
+
+    #[test]
+    fn test_schedule() {
+        let bus_list = vec![Some(7), Some(13), None, Some(59)];
+
+        assert_eq!(departures(&bus_list, 10, 30), vec![
+            (7, vec![14, 21, 28]),
+            (13, vec![13, 26]),
+            (59, vec![]),
+        ]);
+        assert_eq!(departures(&bus_list, 14, 14), vec![(7, vec![14]), (13, vec![]), (59, vec![])]);
+
+        assert_eq!(departing_within(&bus_list, 939, 5), vec![(59, 944)]);
+        assert_eq!(departing_within(&bus_list, 939, 6), vec![(59, 944), (7, 945)]);
+        assert_eq!(departing_within(&bus_list, 944, 0), vec![(59, 944)]);
+
+        assert_eq!(next_together(&[7, 13], 0), 0);
+        assert_eq!(next_together(&[7, 13], 1), 91);
+        assert_eq!(next_together(&[7, 14], 100), 112);
+        assert_eq!(next_together(&[], 5), 5);
+    }
+}
@@ -6,106 +6,320 @@ extern crate regex;
 
 use aoc2020::utils;
 use regex::Regex;
+use itertools::Itertools;
 use std::collections::HashMap;
+use std::env;
+
+const MASK_WIDTH: usize = 36;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Instr {
+    SetMask { ones: u64, zeros: u64, floating: u64 },
+    Write { addr: u64, value: u64 },
+}
+
+// V1 masks the values written, V2 masks the addresses written to
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum DecoderVersion {
+    V1,
+    V2,
+}
+
+fn parse_mask(bits: &str) -> Result<(u64, u64, u64), String> {
+    if bits.len() != MASK_WIDTH {
+        return Err(format!("mask must be {} bits, got {}", MASK_WIDTH, bits.len()));
+    }
 
-fn parse_mask(line: &String) -> (u64, u64, u64) {
     let mut one_mask: u64 = 0;
     let mut zero_mask: u64 = 0;
     let mut float_mask: u64 = 0;
 
-    // skip "mask = "
-    let bits = line.chars().skip(7);
-    bits.for_each(|b| {
+    for b in bits.chars() {
+        one_mask <<= 1;
+        zero_mask <<= 1;
+        float_mask <<= 1;
+
         match b {
-            '1' => one_mask = one_mask | 0x1,
-            '0' => zero_mask = zero_mask | 0x1,
-            'X' => float_mask = float_mask | 0x1,
-            _ => panic!(),
+            '1' => one_mask |= 0x1,
+            '0' => zero_mask |= 0x1,
+            'X' => float_mask |= 0x1,
+            _ => return Err(format!("invalid mask bit '{}'", b)),
         };
+    }
 
-        one_mask = one_mask << 1;
-        zero_mask = zero_mask << 1;
-        float_mask = float_mask << 1;
-    });
-
-    // We over shift in our loop, shift it back down
-    (one_mask >> 1, zero_mask >> 1, float_mask >> 1)
+    Ok((one_mask, zero_mask, float_mask))
 }
 
+fn parse_number(s: &str) -> Result<u64, String> {
+    let n = s.parse::<u64>().map_err(|e| format!("bad number '{}': {}", s, e))?;
+    if n >> MASK_WIDTH != 0 {
+        return Err(format!("{} does not fit in {} bits", n, MASK_WIDTH));
+    }
+
+    Ok(n)
+}
 
-fn parse_mem(line: &String) -> (u64, u64) {
+fn parse_instruction(line: &str) -> Result<Instr, String> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"^mem\[(\d+)\] = (\d+)$").unwrap();
+        static ref MASK_RE: Regex = Regex::new(r"^mask = (\S*)$").unwrap();
+        static ref MEM_RE: Regex = Regex::new(r"^mem\[(\d+)\] = (\d+)$").unwrap();
+    }
+
+    if let Some(cap) = MASK_RE.captures(line) {
+        let (ones, zeros, floating) = parse_mask(&cap[1])?;
+        Ok(Instr::SetMask { ones, zeros, floating })
+    } else if let Some(cap) = MEM_RE.captures(line) {
+        Ok(Instr::Write { addr: parse_number(&cap[1])?, value: parse_number(&cap[2])? })
+    } else {
+        Err(format!("unknown instruction '{}'", line))
     }
+}
+
+fn parse_program(data: &[String]) -> Result<Vec<Instr>, String> {
+    data.iter()
+        .enumerate()
+        .map(|(i, line)| parse_instruction(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+// Expanding a V2 write touches 2^floating addresses, so refuse to build concrete memory past this
+const MAX_EXPANDED_FLOATING_BITS: u32 = 20;
+
+// The set of addresses matching fixed on every bit outside floating, with fixed zero on floating bits
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+struct AddressPattern {
+    fixed: u64,
+    floating: u64,
+}
+
+impl AddressPattern {
+    // The addresses a V2 write to addr goes to under the given mask
+    fn new(addr: u64, one_mask: u64, float_mask: u64) -> AddressPattern {
+        AddressPattern { fixed: (addr | one_mask) & !float_mask, floating: float_mask }
+    }
+
+    fn count(&self) -> u128 {
+        1 << self.floating.count_ones()
+    }
+
+    fn intersect(&self, other: &AddressPattern) -> Option<AddressPattern> {
+        let both_fixed = !self.floating & !other.floating;
+        if (self.fixed ^ other.fixed) & both_fixed != 0 {
+            return None;
+        }
+
+        let floating = self.floating & other.floating;
+        Some(AddressPattern { fixed: (self.fixed | other.fixed) & !floating, floating })
+    }
+
+    fn addresses(&self) -> Vec<u64> {
+        let mut addresses = vec![self.fixed];
+        for i in 0..MASK_WIDTH {
+            if self.floating & (1 << i) != 0 {
+                let set: Vec<u64> = addresses.iter().map(|a| a | (1 << i)).collect();
+                addresses.extend(set);
+            }
+        }
 
-    let cap = RE.captures_iter(line).next().unwrap();
+        addresses
+    }
+}
 
-    (cap[1].parse::<u64>().unwrap(), cap[2].parse::<u64>().unwrap())
+// Runs the program from an empty memory. Until the first mask it writes as if there were no mask,
+// which both decoders treat as a no-op.
+fn run(program: &[Instr], version: DecoderVersion) -> Result<HashMap<u64, u64>, String> {
+    run_with(program, version, |_, _| {})
 }
 
-fn part1(data: &Vec<String>) -> u64 {
+// As run, calling on_block with each mask's index and the memory once that mask's writes are done
+fn run_with<F>(program: &[Instr], version: DecoderVersion, mut on_block: F) -> Result<HashMap<u64, u64>, String>
+where
+    F: FnMut(usize, &HashMap<u64, u64>),
+{
+    let mut block: Option<usize> = None;
     let mut one_mask: u64 = 0;
     let mut zero_mask: u64 = 0;
+    let mut float_mask: u64 = 0;
     let mut memory_map: HashMap<u64, u64> = HashMap::new();
 
-    data.iter().for_each(|line| {
-        if line.starts_with("mask") {
-            let new_mask = parse_mask(line);
-            one_mask = new_mask.0;
-            zero_mask = new_mask.1;
-        } else {
-            let (addr, mut value) = parse_mem(line);
-            value = value & !zero_mask;
-            value = value | one_mask;
-            memory_map.insert(addr, value);
+    for instr in program {
+        match *instr {
+            Instr::SetMask { ones, zeros, floating } => {
+                if let Some(b) = block {
+                    on_block(b, &memory_map);
+                }
+                block = Some(block.map_or(0, |b| b + 1));
+                one_mask = ones;
+                zero_mask = zeros;
+                float_mask = floating;
+            }
+            Instr::Write { addr, value } => match version {
+                DecoderVersion::V1 => {
+                    memory_map.insert(addr, value & !zero_mask | one_mask);
+                }
+                DecoderVersion::V2 => {
+                    if float_mask.count_ones() > MAX_EXPANDED_FLOATING_BITS {
+                        return Err(format!(
+                            "mem[{}] would write to 2^{} addresses",
+                            addr,
+                            float_mask.count_ones()
+                        ));
+                    }
+                    for a in AddressPattern::new(addr, one_mask, float_mask).addresses() {
+                        memory_map.insert(a, value);
+                    }
+                }
+            },
         }
-    });
+    }
+
+    if let Some(b) = block {
+        on_block(b, &memory_map);
+    }
 
-    memory_map.values().sum()
+    Ok(memory_map)
 }
 
-fn update_address_with_float(memory_map: &mut HashMap<u64, u64>, float_mask: u64, addr: u64, value: u64) {
-    if float_mask == 0 {
-        memory_map.insert(addr, value);
-    } else {
-        for i in 0..36 {
-            if float_mask & (1 << i) != 0 {
-                // Remove the float bit
-                let new_float_mask = float_mask & !(1 << i);
-                // Recursively assign both
-                update_address_with_float(memory_map, new_float_mask, addr | (1 << i), value);
-                update_address_with_float(memory_map, new_float_mask, addr & (!(1 << i)), value);
-
-                break;
-            }
-        }
+// Writes floating most of their bits can overlap in exponentially many ways, so give up once
+// tracking them takes more than this many patterns, a few hundred MB
+const MAX_OVERLAP_PATTERNS: usize = 1 << 22;
+
+fn add_count(counts: &mut HashMap<AddressPattern, i64>, pattern: AddressPattern, delta: i64) {
+    let count = counts.entry(pattern).or_insert(0);
+    *count += delta;
+    if *count == 0 {
+        counts.remove(&pattern);
     }
 }
 
-fn part2(data: &Vec<String>) -> u64 {
+// Works backwards through the V2 writes, so each write only counts for the addresses that no
+// later write overwrites. The addresses written so far are kept by inclusion-exclusion, as signed
+// counts of patterns that add up to one on every address covered and zero everywhere else.
+// Intersections that come up more than once share an entry and entries that cancel out are
+// dropped, so memory grows with the number of distinct overlaps between writes, not the number
+// of addresses.
+fn memory_sum_v2(program: &[Instr], max_patterns: usize) -> Result<u128, String> {
     let mut one_mask: u64 = 0;
     let mut float_mask: u64 = 0;
-    let mut memory_map: HashMap<u64, u64> = HashMap::new();
+    let mut writes: Vec<(AddressPattern, u64)> = Vec::new();
+
+    for instr in program {
+        match *instr {
+            Instr::SetMask { ones, floating, .. } => {
+                one_mask = ones;
+                float_mask = floating;
+            }
+            Instr::Write { addr, value } => writes.push((AddressPattern::new(addr, one_mask, float_mask), value)),
+        }
+    }
+
+    let mut later: HashMap<AddressPattern, i64> = HashMap::new();
+    let mut sum: u128 = 0;
+    for (pattern, value) in writes.iter().rev() {
+        let overlaps: Vec<(AddressPattern, i64)> = later.iter()
+            .filter_map(|(overwritten, count)| pattern.intersect(overwritten).map(|p| (p, *count)))
+            .collect();
+        let overwritten: i128 = overlaps.iter().map(|(p, count)| p.count() as i128 * *count as i128).sum();
+        sum += *value as u128 * (pattern.count() as i128 - overwritten) as u128;
+
+        // Adding pattern to the union takes away what it already shared with it
+        for (p, count) in overlaps {
+            add_count(&mut later, p, -count);
+        }
+        add_count(&mut later, *pattern, 1);
 
-    data.iter().for_each(|line| {
-        if line.starts_with("mask") {
-            let new_mask = parse_mask(line);
-            one_mask = new_mask.0;
-            float_mask = new_mask.2;
-        } else {
-            let (mut addr, value) = parse_mem(line);
-            addr = addr | one_mask;
-            update_address_with_float(&mut memory_map, float_mask, addr, value);
+        if later.len() > max_patterns {
+            return Err(format!("writes overlap in more than {} ways", max_patterns));
         }
-    });
+    }
+
+    Ok(sum)
+}
+
+fn memory_sum(program: &[Instr], version: DecoderVersion) -> Result<u128, String> {
+    match version {
+        DecoderVersion::V1 => run(program, version).map(|memory_map| memory_map.values().map(|v| *v as u128).sum()),
+        DecoderVersion::V2 => memory_sum_v2(program, MAX_OVERLAP_PATTERNS),
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum DumpFormat {
+    Decimal,
+    Hex,
+}
+
+fn format_number(n: u64, format: DumpFormat) -> String {
+    match format {
+        DumpFormat::Decimal => n.to_string(),
+        DumpFormat::Hex => format!("0x{:09x}", n),
+    }
+}
+
+// One "addr = value" line per address written, by address
+fn dump_memory(memory_map: &HashMap<u64, u64>, format: DumpFormat) -> Vec<String> {
+    memory_map.iter()
+        .sorted()
+        .map(|(addr, value)| format!("{} = {}", format_number(*addr, format), format_number(*value, format)))
+        .collect()
+}
+
+// (addr, v1 value, v2 value) for every address where the two memories differ, by address
+fn diff_memory(v1: &HashMap<u64, u64>, v2: &HashMap<u64, u64>) -> Vec<(u64, Option<u64>, Option<u64>)> {
+    v1.keys()
+        .chain(v2.keys())
+        .unique()
+        .sorted()
+        .map(|addr| (*addr, v1.get(addr).copied(), v2.get(addr).copied()))
+        .filter(|(_, a, b)| a != b)
+        .collect()
+}
 
-    memory_map.values().sum()
+fn format_diff(diff: &[(u64, Option<u64>, Option<u64>)], format: DumpFormat) -> Vec<String> {
+    let value = |v: &Option<u64>| v.map_or(String::from("-"), |v| format_number(v, format));
+    diff.iter()
+        .map(|(addr, a, b)| format!("{}: v1 = {}, v2 = {}", format_number(*addr, format), value(a), value(b)))
+        .collect()
 }
 
 fn main() {
     let data: Vec<String> = utils::read_lines("./input_data/14.txt");
-    println!("Part 1: {}", part1(&data));
-    println!("Part 2: {}", part2(&data));
+    let program = match parse_program(&data) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    // e.g. "dump v2 --hex --blocks" or "diff"
+    let args: Vec<String> = env::args().collect();
+    let format = if args.iter().any(|a| a == "--hex") { DumpFormat::Hex } else { DumpFormat::Decimal };
+    let version = if args.iter().any(|a| a == "v2") { DecoderVersion::V2 } else { DecoderVersion::V1 };
+    let result = match args.get(1).map(String::as_str) {
+        Some("dump") if args.iter().any(|a| a == "--blocks") => run_with(&program, version, |block, memory_map| {
+            println!("# after mask {}", block);
+            dump_memory(memory_map, format).iter().for_each(|line| println!("{}", line));
+        })
+        .map(|_| ()),
+        Some("dump") => run(&program, version).map(|memory_map| {
+            dump_memory(&memory_map, format).iter().for_each(|line| println!("{}", line));
+        }),
+        Some("diff") => run(&program, DecoderVersion::V1).and_then(|v1| {
+            let v2 = run(&program, DecoderVersion::V2)?;
+            format_diff(&diff_memory(&v1, &v2), format).iter().for_each(|line| println!("{}", line));
+            Ok(())
+        }),
+        _ => memory_sum(&program, DecoderVersion::V1).and_then(|part1| {
+            println!("Part 1: {}", part1);
+            println!("Part 2: {}", memory_sum(&program, DecoderVersion::V2)?);
+            Ok(())
+        }),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+    }
 }
 
 #[cfg(test)]
@@ -114,17 +328,45 @@ mod tests {
 
     #[test]
     fn test_parse_mask() {
-        let (one, zero, float) = parse_mask(&String::from("mask = 1X0X0"));
-        assert_eq!(one, 0b10000);
-        assert_eq!(zero, 0b00101);
-        assert_eq!(float, 0b01010);
+        let (one, zero, float) = parse_mask("0000000000000000000000000000000X1X0X").unwrap();
+        assert_eq!(one, 0b1000);
+        assert_eq!(zero, 0xFFFFFFFE2);
+        assert_eq!(float, 0b10101);
+
+        assert!(parse_mask("1X0X0").is_err());
+        assert!(parse_mask("0000000000000000000000000000000X1X0X0").is_err());
+        assert!(parse_mask("0000000000000000000000000000000X1X0Y").is_err());
     }
 
     #[test]
-    fn test_parse_mem() {
-        let (addr, value) = parse_mem(&String::from("mem[41026] = 409998"));
-        assert_eq!(addr, 41026);
-        assert_eq!(value, 409998);
+    fn test_parse_instruction() {
+        assert_eq!(parse_instruction("mem[41026] = 409998"), Ok(Instr::Write { addr: 41026, value: 409998 }));
+        assert_eq!(
+            parse_instruction("mask = 000000000000000000000000000000000000"),
+            Ok(Instr::SetMask { ones: 0, zeros: 0xFFFFFFFFF, floating: 0 })
+        );
+        assert!(parse_instruction("mem[1] = 68719476736").is_err());
+        assert!(parse_instruction("mem[1] = 68719476735").is_ok());
+        assert!(parse_instruction("mem[1] = -1").is_err());
+        assert!(parse_instruction("nop").is_err());
+        assert!(parse_instruction("mask = X").is_err());
+    }
+
+    #[test]
+    fn test_parse_program() {
+        let data = vec![String::from("mem[1] = 2"), String::from("memory[1] = 2")];
+        assert_eq!(parse_program(&data), Err(String::from("line 2: unknown instruction 'memory[1] = 2'")));
+    }
+
+    #[test]
+    fn test_part1() {
+        let data = vec![
+            String::from("mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X"),
+            String::from("mem[8] = 11"),
+            String::from("mem[7] = 101"),
+            String::from("mem[8] = 0"),
+        ];
+        assert_eq!(memory_sum(&parse_program(&data).unwrap(), DecoderVersion::V1), Ok(165))
     }
 
     #[test]
@@ -135,6 +377,154 @@ mod tests {
             String::from("mask = 00000000000000000000000000000000X0XX"),
             String::from("mem[26] = 1")
         ];
-        assert_eq!(part2(&data), 208)
+        assert_eq!(memory_sum(&parse_program(&data).unwrap(), DecoderVersion::V2), Ok(208))
+    }
+
+    #[test]
+    fn test_address_pattern() {
+        let a = AddressPattern::new(0b0000, 0, 0b0110);
+        let b = AddressPattern::new(0b0001, 0, 0b0100);
+        assert_eq!(a.count(), 4);
+        assert_eq!(a.intersect(&b), None);
+
+        let c = AddressPattern::new(0b0000, 0, 0b0011);
+        assert_eq!(a.intersect(&c), Some(AddressPattern { fixed: 0, floating: 0b0010 }));
+    }
+
+    #[test]
+    fn test_intersect_matches_sets() {
+        // Every pair of 4-bit patterns, compared against plain address sets
+        let patterns: Vec<AddressPattern> = (0..16u64)
+            .flat_map(|floating| (0..16u64).map(move |addr| AddressPattern::new(addr, 0, floating)))
+            .collect();
+        for a in &patterns {
+            for b in &patterns {
+                let b_addresses = b.addresses();
+                let mut expected: Vec<u64> = a.addresses().into_iter().filter(|x| b_addresses.contains(x)).collect();
+                let mut both: Vec<u64> = a.intersect(b).map_or(Vec::new(), |p| p.addresses());
+                expected.sort();
+                both.sort();
+                assert_eq!(both, expected, "{:?} & {:?}", a, b);
+            }
+        }
+    }
+
+    // A random program of masks each floating about floating_percent of the low bits, and each
+    // followed by a few writes
+    fn random_program(seed: u64, masks: usize, bits: usize, floating_percent: u64) -> Vec<Instr> {
+        let mut state = seed;
+        let mut next = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            state >> 33
+        };
+
+        let low_bits = (1u64 << bits) - 1;
+        let mut program = Vec::new();
+        for _ in 0..masks {
+            let floating = (0..bits).filter(|_| next() % 100 < floating_percent).fold(0, |f, b| f | 1 << b);
+            let ones = next() & low_bits & !floating;
+            program.push(Instr::SetMask { ones, zeros: !ones & !floating & ((1 << MASK_WIDTH) - 1), floating });
+            for _ in 0..1 + next() % 5 {
+                program.push(Instr::Write { addr: next() & low_bits, value: next() % 1000 });
+            }
+        }
+
+        program
+    }
+
+    #[test]
+    fn test_part2_random_matches_run() {
+        for seed in 0..200 {
+            let program = random_program(seed, 20, 10, 50);
+            let expected: u128 = run(&program, DecoderVersion::V2).unwrap().values().map(|v| *v as u128).sum();
+            assert_eq!(memory_sum(&program, DecoderVersion::V2), Ok(expected), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_part2_heavy_floating() {
+        // Over 100 writes floating about 27 of the 36 bits, which splitting into disjoint pieces
+        // took seconds to untangle
+        let mut program = random_program(1, 30, MASK_WIDTH, 75);
+        assert!(program.iter().filter(|instr| matches!(instr, Instr::Write { .. })).count() > 100);
+        assert_eq!(memory_sum(&program, DecoderVersion::V2), Ok(11243937570337));
+        assert!(memory_sum_v2(&program, 100).is_err());
+
+        // With every value 1 the sum is the number of addresses written, and a first write to
+        // every address fills in exactly what the rest leave untouched
+        for instr in program.iter_mut() {
+            if let Instr::Write { value, .. } = instr {
+                *value = 1;
+            }
+        }
+        program.splice(0..0, vec![
+            Instr::SetMask { ones: 0, zeros: 0, floating: (1 << MASK_WIDTH) - 1 },
+            Instr::Write { addr: 0, value: 1 },
+        ]);
+        assert_eq!(memory_sum(&program, DecoderVersion::V2), Ok(1 << MASK_WIDTH));
+    }
+
+    #[test]
+    fn test_part2_symbolic_matches_run() {
+        let data = vec![
+            String::from("mask = 0000000000000000000000000000000XX1XX"),
+            String::from("mem[3] = 10"),
+            String::from("mask = 00000000000000000000000000000000X0X1"),
+            String::from("mem[8] = 7"),
+            String::from("mem[2] = 3"),
+            String::from("mask = 000000000000000000000000000000XX0000"),
+            String::from("mem[1] = 5"),
+        ];
+        let program = parse_program(&data).unwrap();
+        let expected: u128 = run(&program, DecoderVersion::V2).unwrap().values().map(|v| *v as u128).sum();
+        assert_eq!(memory_sum(&program, DecoderVersion::V2), Ok(expected));
+    }
+
+    #[test]
+    fn test_part2_many_floating_bits() {
+        let data = vec![
+            String::from("mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX"),
+            String::from("mem[0] = 2"),
+            String::from("mask = 0XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX"),
+            String::from("mem[0] = 1"),
+        ];
+        let program = parse_program(&data).unwrap();
+        assert_eq!(memory_sum(&program, DecoderVersion::V2), Ok((1u128 << 35) * 2 + (1u128 << 35)));
+        assert!(run(&program, DecoderVersion::V2).is_err());
+    }
+
+    #[test]
+    fn test_dump_and_diff() {
+        let data = vec![
+            String::from("mask = 000000000000000000000000000000X1001X"),
+            String::from("mem[42] = 100"),
+            String::from("mask = 00000000000000000000000000000000X0XX"),
+            String::from("mem[26] = 1"),
+        ];
+        let program = parse_program(&data).unwrap();
+
+        let mut blocks: Vec<(usize, Vec<String>)> = Vec::new();
+        let v2 = run_with(&program, DecoderVersion::V2, |block, memory_map| {
+            blocks.push((block, dump_memory(memory_map, DumpFormat::Decimal)))
+        })
+        .unwrap();
+        assert_eq!(blocks, vec![
+            (0, vec![String::from("26 = 100"), String::from("27 = 100"), String::from("58 = 100"), String::from("59 = 100")]),
+            (1, dump_memory(&v2, DumpFormat::Decimal)),
+        ]);
+        assert_eq!(blocks[1].1[0], "16 = 1");
+
+        let v1 = run(&program, DecoderVersion::V1).unwrap();
+        assert_eq!(dump_memory(&v1, DumpFormat::Hex), vec![
+            String::from("0x00000001a = 0x000000001"),
+            String::from("0x00000002a = 0x000000032"),
+        ]);
+
+        let diff = diff_memory(&v1, &v2);
+        assert_eq!(diff[0], (16, None, Some(1)));
+        assert!(diff.iter().all(|(addr, _, _)| *addr != 26));
+        assert!(diff.contains(&(42, Some(50), None)));
+        assert_eq!(format_diff(&diff[..1], DumpFormat::Decimal), vec![String::from("16: v1 = -, v2 = 1")]);
+        assert!(diff_memory(&v1, &v1).is_empty());
     }
-}
\ No newline at end of file
+}
@@ -1,41 +1,262 @@
 extern crate aoc2020;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 
-fn part1(data: Vec<usize>, num_rounds: usize) -> usize {
-    let mut spoken_numbers_to_round: HashMap<usize, usize> = HashMap::new();
+// Numbers below this get a slot in the flat turn table, larger ones go in the sparse map.
+// 2^28 u32 slots is a 1 GiB table.
+const DEFAULT_DENSE_LIMIT: usize = 1 << 28;
+
+// Bytes per bucket of the sparse map, a (number, turn) pair and a control byte
+const SPARSE_BUCKET_BYTES: usize = 2 * std::mem::size_of::<u32>() + 1;
+
+// Control bytes the sparse map keeps past its last bucket, one SIMD group's worth
+const SPARSE_GROUP_WIDTH: usize = 16;
+
+// Bytes the sparse map allocates once it can hold capacity entries. Buckets come in powers of
+// two and are kept at most 7/8 full, except that small tables can fill all but one.
+fn sparse_table_bytes(capacity: usize) -> usize {
+    if capacity == 0 {
+        return 0;
+    }
+
+    let buckets = if capacity < 8 {
+        (capacity + 1).next_power_of_two().max(4)
+    } else {
+        (capacity * 8 / 7).next_power_of_two()
+    };
+    buckets * SPARSE_BUCKET_BYTES + SPARSE_GROUP_WIDTH
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Turn {
+    turn: u32,
+    number: u32,
+    // Turns since number was last spoken, 0 if it's new. After the starting numbers, this is also
+    // the next number spoken.
+    age: u32,
+}
+
+// The Van Eck style memory game as an iterator over every number spoken, starting numbers included
+struct MemoryGame {
+    starting: Vec<u32>,
+    // Turn (1-based) each number was last spoken on, 0 for never
+    dense: Vec<u32>,
+    dense_limit: usize,
+    sparse: HashMap<u32, u32>,
+    // Bytes the sparse map may grow to, the game stops rather than go past it
+    sparse_budget: usize,
+    over_budget: bool,
+    turn: u32,
+    next: u32,
+}
+
+impl MemoryGame {
+    fn new(starting: &[usize]) -> MemoryGame {
+        MemoryGame::with_dense_limit(starting, DEFAULT_DENSE_LIMIT)
+    }
+
+    fn with_dense_limit(starting: &[usize], dense_limit: usize) -> MemoryGame {
+        MemoryGame {
+            starting: starting.iter().map(|n| *n as u32).collect(),
+            dense: Vec::new(),
+            dense_limit,
+            sparse: HashMap::new(),
+            sparse_budget: usize::MAX,
+            over_budget: false,
+            turn: 0,
+            next: 0,
+        }
+    }
 
-    data.iter().enumerate()
-        .for_each(|(round, number)| {
-            spoken_numbers_to_round.insert(number.clone(), round);
-        });
+    // A game whose turn tables stay within budget bytes. Every number spoken in a game of rounds
+    // turns is below rounds, so if a dense slot for each fits that's all it needs. Otherwise half
+    // the budget goes on dense slots and the rest on the sparse map, and the game ends early if
+    // the sparse map runs out of room.
+    fn with_budget(starting: &[usize], rounds: usize, budget: usize) -> MemoryGame {
+        let slot = std::mem::size_of::<u32>();
+        let all_dense = rounds.max(starting.len()) + 1;
+        let limit = if all_dense.saturating_mul(slot) <= budget { all_dense } else { budget / 2 / slot };
 
-    let mut prev_number = 0;
-    let mut current_number = 0;
+        let mut game = MemoryGame::with_dense_limit(starting, limit);
+        game.sparse_budget = budget - limit * slot;
+        game
+    }
 
-    for round in data.len()..num_rounds {
-        let mut next_number = 0;
+    fn out_of_budget(&self) -> bool {
+        self.over_budget
+    }
 
-        let last_time_spoken = spoken_numbers_to_round.get(&current_number);
-        if last_time_spoken.is_some() {
-            next_number = round - last_time_spoken.unwrap()
+    // Records number as spoken on turn, returning the turn it was previously spoken on or 0. None
+    // if the sparse map would have to grow past its budget, in which case nothing is recorded.
+    fn speak(&mut self, number: u32, turn: u32) -> Option<u32> {
+        let n = number as usize;
+        if n < self.dense_limit {
+            if n >= self.dense.len() {
+                // Grow by doubling so the table only gets as large as the numbers seen
+                let len = (n + 1).max(self.dense.len() * 2).min(self.dense_limit);
+                self.dense.reserve_exact(len - self.dense.len());
+                self.dense.resize(len, 0);
+            }
+            Some(std::mem::replace(&mut self.dense[n], turn))
+        } else {
+            let full = self.sparse.len() == self.sparse.capacity();
+            if full && !self.sparse.contains_key(&number) {
+                // The map moves to the next size up that fits one more entry
+                let grown = sparse_table_bytes(self.sparse.capacity() + 1);
+                if grown > self.sparse_budget {
+                    self.over_budget = true;
+                    return None;
+                }
+            }
+            Some(self.sparse.insert(number, turn).unwrap_or(0))
         }
+    }
+
+    // The next turn in full, the iterator only gives the numbers
+    fn speak_next(&mut self) -> Option<Turn> {
+        // Numbers spoken are always below the turn, so the turn overflows first
+        let turn = self.turn.checked_add(1)?;
 
-        spoken_numbers_to_round.insert(current_number, round);
+        let number = match self.starting.get(turn as usize - 1) {
+            Some(n) => *n,
+            None => self.next,
+        };
 
-        prev_number = current_number;
-        current_number = next_number;
+        let previous = self.speak(number, turn)?;
+        self.turn = turn;
+        self.next = if previous == 0 { 0 } else { turn - previous };
+
+        Some(Turn { turn, number, age: self.next })
+    }
+
+    fn turns(mut self) -> impl Iterator<Item = Turn> {
+        std::iter::from_fn(move || self.speak_next())
+    }
+
+    // Bytes used by the turn tables
+    fn memory_used(&self) -> usize {
+        self.dense.capacity() * std::mem::size_of::<u32>() + sparse_table_bytes(self.sparse.capacity())
+    }
+}
+
+impl Iterator for MemoryGame {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        self.speak_next().map(|t| t.number)
+    }
+}
+
+fn part1(data: Vec<usize>, num_rounds: usize) -> usize {
+    MemoryGame::new(&data).nth(num_rounds - 1).unwrap() as usize
+}
+
+#[derive(Debug, PartialEq)]
+struct Stats {
+    terms: Vec<u32>,
+    // How often each number comes up, by number
+    frequencies: BTreeMap<u32, usize>,
+    // The turn the value being looked for is first spoken on
+    first_occurrence: Option<u32>,
+    // The earliest turn with the largest age, i.e. the longest wait between repeats
+    largest_gap: Option<Turn>,
+}
+
+// Everything about the first rounds turns, from a single play through
+fn stats(starting: &[usize], rounds: usize, find: Option<u32>) -> Stats {
+    let mut stats = Stats { terms: vec![], frequencies: BTreeMap::new(), first_occurrence: None, largest_gap: None };
+
+    for t in MemoryGame::new(starting).turns().take(rounds) {
+        stats.terms.push(t.number);
+        *stats.frequencies.entry(t.number).or_insert(0) += 1;
+        if stats.first_occurrence.is_none() && Some(t.number) == find {
+            stats.first_occurrence = Some(t.turn);
+        }
+        if stats.largest_gap.is_none_or(|g| t.age > g.age) {
+            stats.largest_gap = Some(t);
+        }
     }
 
-    println!("Hash map size: {}", spoken_numbers_to_round.len());
+    stats
+}
+
+fn to_csv(starting: &[usize], rounds: usize) -> String {
+    let mut csv = String::from("turn,number,age\n");
+    for t in MemoryGame::new(starting).turns().take(rounds) {
+        csv += &format!("{},{},{}\n", t.turn, t.number, t.age);
+    }
 
-    prev_number
+    csv
 }
 
 fn main() {
     let input = vec![1, 12, 0, 20, 8, 16];
+
+    let args: Vec<String> = env::args().collect();
+
+    // e.g. "stats 100 --find 42" or "csv 1000"
+    match args.get(1).map(String::as_str) {
+        Some("stats") => {
+            let k = args[2].parse::<usize>().unwrap();
+            let find = args.iter().position(|a| a == "--find").map(|i| args[i + 1].parse::<u32>().unwrap());
+            let stats = stats(&input, k, find);
+
+            let terms: Vec<String> = stats.terms.iter().map(|n| n.to_string()).collect();
+            println!("First {}: {}", k, terms.join(", "));
+            for (number, count) in &stats.frequencies {
+                println!("{}: {}", number, count);
+            }
+            if let Some(t) = stats.largest_gap {
+                println!("Largest gap: {} turns, {} on turn {} since it was last spoken", t.age, t.number, t.turn);
+            }
+            if let Some(value) = find {
+                match stats.first_occurrence {
+                    Some(turn) => println!("{} first spoken on turn {}", value, turn),
+                    None => println!("{} not spoken in the first {} turns", value, k),
+                }
+            }
+            return;
+        }
+        Some("csv") => {
+            print!("{}", to_csv(&input, args[2].parse::<usize>().unwrap()));
+            return;
+        }
+        _ => {}
+    }
+
+    // e.g. "1000000000 --budget 4096" for the billionth number using at most 4 GiB
+    if let Some(rounds) = args.get(1).and_then(|a| a.parse::<usize>().ok()) {
+        if rounds == 0 {
+            eprintln!("there's no number 0, turns start at 1");
+            return;
+        }
+
+        let mut game = match args.iter().position(|a| a == "--budget") {
+            Some(i) => match args.get(i + 1).map(|b| b.parse::<usize>()) {
+                Some(Ok(mib)) => MemoryGame::with_budget(&input, rounds, mib.saturating_mul(1 << 20)),
+                Some(Err(e)) => {
+                    eprintln!("bad budget: {}", e);
+                    return;
+                }
+                None => {
+                    eprintln!("--budget needs a size in MiB");
+                    return;
+                }
+            },
+            None => MemoryGame::new(&input),
+        };
+        match game.nth(rounds - 1) {
+            Some(n) => println!("Number {}: {}", rounds, n),
+            None if game.out_of_budget() => println!("Ran out of memory budget after {} rounds", game.turn),
+            None => println!("The game can't go on for {} rounds", rounds),
+        }
+        println!("Memory used: {} MiB", game.memory_used() >> 20);
+        return;
+    }
+
     println!("Part 1: {}", part1(input.clone(), 2020));
-    println!("Part 2: {}", part1(input.clone(), 30000000));
+    println!("Part 2: {}", part1(input, 30000000));
 }
 
 #[cfg(test)]
@@ -53,4 +274,84 @@ mod tests {
         assert_eq!(part1(vec![0, 3, 6], 10), 0);
         assert_eq!(part1(vec![0, 3, 6], 2020), 436);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_iterator() {
+        let numbers: Vec<u32> = MemoryGame::new(&[0, 3, 6]).take(10).collect();
+        assert_eq!(numbers, vec![0, 3, 6, 0, 3, 3, 1, 0, 4, 0]);
+
+        // A repeated starting number counts as spoken again
+        let numbers: Vec<u32> = MemoryGame::new(&[1, 1]).take(5).collect();
+        assert_eq!(numbers, vec![1, 1, 1, 1, 1]);
+        assert_eq!(MemoryGame::new(&[]).take(3).collect::<Vec<u32>>(), vec![0, 0, 1]);
+    }
+
+    #[test]
+    fn test_sparse_matches_dense() {
+        for limit in [0, 1, 7, 100].iter() {
+            let sparse = MemoryGame::with_dense_limit(&[2, 1, 3], *limit);
+            assert!(sparse.zip(MemoryGame::new(&[2, 1, 3])).take(50_000).all(|(a, b)| a == b));
+        }
+
+        // Every turn fits in the dense table
+        assert_eq!(MemoryGame::with_budget(&[3, 1, 2], 2020, 10_000).nth(2019), Some(1836));
+        let mut game = MemoryGame::with_budget(&[3, 1, 2], 2020, 4096);
+        assert_eq!(game.nth(2019), Some(1836));
+        assert!(game.memory_used() <= 4096);
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut game = MemoryGame::with_budget(&[0, 3, 6], 1_000_000, 1024);
+        assert_eq!(game.nth(999_999), None);
+        assert!(game.out_of_budget());
+        assert!(game.turn > 128);
+
+        // Worked out from the capacity the map reports, which is all but one of a small table's
+        // buckets or 7/8 of a bigger one's
+        let capacity = game.sparse.capacity();
+        let buckets = if capacity < 8 { capacity + 1 } else { capacity / 7 * 8 };
+        assert!(!game.sparse.is_empty());
+        assert!(game.dense.capacity() * 4 + buckets * 9 + 16 <= 1024);
+        assert_eq!(game.memory_used(), game.dense.capacity() * 4 + buckets * 9 + 16);
+
+        // Stopping leaves the game where it was
+        assert_eq!(game.next(), None);
+
+        let mut game = MemoryGame::with_budget(&[0, 3, 6], 1_000_000, 64 << 20);
+        assert_eq!(game.nth(999_999), MemoryGame::new(&[0, 3, 6]).nth(999_999));
+        assert!(!game.out_of_budget());
+    }
+
+    #[test]
+    fn test_sparse_table_bytes() {
+        assert_eq!(sparse_table_bytes(0), 0);
+        assert_eq!(sparse_table_bytes(1), 4 * 9 + 16);
+        assert_eq!(sparse_table_bytes(3), 4 * 9 + 16);
+        assert_eq!(sparse_table_bytes(4), 8 * 9 + 16);
+        assert_eq!(sparse_table_bytes(14), 16 * 9 + 16);
+        assert_eq!(sparse_table_bytes(15), 32 * 9 + 16);
+
+        // 100 entries fill a 128 bucket table to 112
+        let map: HashMap<u32, u32> = (0..100).map(|n| (n, n)).collect();
+        assert_eq!(map.capacity(), 112);
+        assert_eq!(sparse_table_bytes(map.capacity()), 128 * 9 + 16);
+    }
+
+    #[test]
+    fn test_analytics() {
+        let all = stats(&[0, 3, 6], 10, Some(4));
+        assert_eq!(all.terms, vec![0, 3, 6, 0, 3, 3, 1, 0, 4, 0]);
+        let counts: Vec<(u32, usize)> = all.frequencies.into_iter().collect();
+        assert_eq!(counts, vec![(0, 4), (1, 1), (3, 3), (4, 1), (6, 1)]);
+        assert_eq!(all.first_occurrence, Some(9));
+        // The 0 on turn 8 was last spoken on turn 4
+        assert_eq!(all.largest_gap, Some(Turn { turn: 8, number: 0, age: 4 }));
+
+        assert_eq!(stats(&[0, 3, 6], 8, Some(4)).first_occurrence, None);
+        assert_eq!(stats(&[0, 3, 6], 10, None).first_occurrence, None);
+        assert_eq!(stats(&[0, 3, 6], 0, Some(0)).largest_gap, None);
+
+        assert_eq!(to_csv(&[0, 3, 6], 4), "turn,number,age\n1,0,0\n2,3,0\n3,6,0\n4,0,3\n");
+    }
+}
@@ -6,61 +6,167 @@ extern crate regex;
 
 use aoc2020::utils;
 use regex::Regex;
-use std::ops::Range;
+use std::cmp::Ordering;
+use std::ops::RangeInclusive;
 
 #[derive(Debug)]
 struct Rule {
     name: String,
-    ranges: Vec<Range<usize>>,
+    // Sorted, non-overlapping and non-adjacent
+    ranges: Vec<RangeInclusive<usize>>,
 }
 
 impl Rule {
     fn includes_value(&self, v: &usize) -> bool {
-        self.ranges.iter().any(|r| r.contains(v))
+        self.ranges
+            .binary_search_by(|r| {
+                if r.end() < v {
+                    Ordering::Less
+                } else if r.start() > v {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .is_ok()
     }
 }
 
 type Ticket = Vec<usize>;
 
-fn parse_rule(line: &String) -> Rule {
+// How many field assignments to list when the tickets don't pin down just one
+const MAX_ASSIGNMENTS: usize = 100;
+
+// Sorts the ranges and merges any that overlap or touch
+fn normalize(mut ranges: Vec<RangeInclusive<usize>>) -> Vec<RangeInclusive<usize>> {
+    ranges.retain(|r| !r.is_empty());
+    ranges.sort_by_key(|r| *r.start());
+
+    let mut merged: Vec<RangeInclusive<usize>> = vec![];
+    for r in ranges {
+        match merged.last_mut() {
+            Some(last) if *r.start() <= last.end().saturating_add(1) => {
+                *last = *last.start()..=*last.end().max(r.end());
+            }
+            _ => merged.push(r),
+        }
+    }
+
+    merged
+}
+
+// The parts of the normalized ranges outside the normalized excluded ranges
+fn subtract(ranges: &[RangeInclusive<usize>], excluded: &[RangeInclusive<usize>]) -> Vec<RangeInclusive<usize>> {
+    let mut result = vec![];
+    for r in ranges {
+        // None once the rest of r is excluded, which also covers an exclusion up to usize::MAX
+        let mut start = Some(*r.start());
+        for e in excluded.iter().filter(|e| e.start() <= r.end() && e.end() >= r.start()) {
+            let s = match start {
+                Some(s) => s,
+                None => break,
+            };
+            if *e.start() > s {
+                result.push(s..=*e.start() - 1);
+            }
+            start = if *e.end() >= s { e.end().checked_add(1) } else { Some(s) };
+        }
+        if let Some(s) = start {
+            if s <= *r.end() {
+                result.push(s..=*r.end());
+            }
+        }
+    }
+
+    result
+}
+
+// One of "10-20", "10", ">= 10", "> 10", "<= 10" or "< 10", optionally after "not ".
+// Returns whether it's negated along with the values it covers.
+fn parse_term(term: &str) -> Result<(bool, RangeInclusive<usize>), String> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"^([^:]+): (\d+)\-(\d+) or (\d+)\-(\d+)$").unwrap();
+        static ref RE: Regex = Regex::new(r"^(not )?(?:(\d+)-(\d+)|(>=|>|<=|<) ?(\d+)|(\d+))$").unwrap();
     }
 
-    let cap = RE.captures_iter(line).next().unwrap();
+    let cap = RE.captures(term).ok_or(format!("invalid range '{}'", term))?;
+    let number = |i: usize| cap[i].parse::<usize>().map_err(|e| format!("bad number '{}': {}", &cap[i], e));
+
+    let range = if cap.get(2).is_some() {
+        let (start, end) = (number(2)?, number(3)?);
+        if start > end {
+            return Err(format!("range '{}' is backwards", term));
+        }
+        start..=end
+    } else if cap.get(4).is_some() {
+        let n = number(5)?;
+        let nothing = || format!("'{}' matches no values", term);
+        match &cap[4] {
+            ">=" => n..=usize::MAX,
+            ">" => n.checked_add(1).ok_or_else(nothing)?..=usize::MAX,
+            "<=" => 0..=n,
+            _ => 0..=n.checked_sub(1).ok_or_else(nothing)?,
+        }
+    } else {
+        let n = number(6)?;
+        n..=n
+    };
+
+    Ok((cap.get(1).is_some(), range))
+}
+
+// "name: term or term or ...". The "not" terms are taken away from the rest, so
+// "1-100 or not 10-20" is 1-9 and 21-100. A rule that's only "not" terms excludes them from
+// everything.
+fn parse_rule(line: &str) -> Result<Rule, String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^([^:]+): (.+)$").unwrap();
+    }
+
+    let cap = RE.captures(line).ok_or(format!("invalid rule '{}'", line))?;
 
     let name: String = cap[1].to_string();
 
-    let ranges = vec![
-        (cap[2].parse::<usize>().unwrap()..(cap[3].parse::<usize>().unwrap()) + 1),
-        (cap[4].parse::<usize>().unwrap()..(cap[5].parse::<usize>().unwrap()) + 1)
-    ];
+    let mut included = vec![];
+    let mut excluded = vec![];
+    for term in cap[2].split(" or ") {
+        match parse_term(term.trim())? {
+            (false, range) => included.push(range),
+            (true, range) => excluded.push(range),
+        }
+    }
+
+    if included.is_empty() {
+        included.push(0..=usize::MAX);
+    }
+
+    let ranges = subtract(&normalize(included), &normalize(excluded));
 
-    Rule { name, ranges }
+    Ok(Rule { name, ranges })
 }
 
-fn parse_ticket(line: String) -> Ticket {
-    line.split(",").map(|v| v.parse::<usize>().unwrap()).collect()
+fn parse_ticket(line: &str) -> Result<Ticket, String> {
+    line.split(',').map(|v| v.parse::<usize>().map_err(|e| format!("bad ticket value '{}': {}", v, e))).collect()
 }
 
-fn parse_file(data: &Vec<String>) -> (Vec<Rule>, Ticket, Vec<Ticket>) {
+fn parse_file(data: &[String]) -> Result<(Vec<Rule>, Ticket, Vec<Ticket>), String> {
     let mut line_iter = data.iter();
+    let truncated = || String::from("file ends early");
 
     let mut rules: Vec<Rule> = vec![];
     loop {
-        let line = line_iter.next().unwrap();
+        let line = line_iter.next().ok_or_else(truncated)?;
 
         if line.is_empty() {
             break;
         }
 
-        rules.push(parse_rule(line));
+        rules.push(parse_rule(line)?);
     }
 
     // Drop the "your ticket:" line
     line_iter.next();
 
-    let ticket = parse_ticket(line_iter.next().unwrap().to_string());
+    let ticket = parse_ticket(line_iter.next().ok_or_else(truncated)?)?;
 
     // Drop the empty line
     line_iter.next();
@@ -68,81 +174,142 @@ fn parse_file(data: &Vec<String>) -> (Vec<Rule>, Ticket, Vec<Ticket>) {
     // Drop the "nearby tickets:" line
     line_iter.next();
 
-    let nearby_tickets: Vec<Ticket> = line_iter.map(|l| parse_ticket(l.to_string())).collect();
+    // Every ticket needs a value for each column, or matching rules to columns would run off the end
+    let first_nearby = data.len() - line_iter.len();
+    let nearby_tickets: Vec<Ticket> = line_iter
+        .enumerate()
+        .map(|(i, l)| {
+            let nearby = parse_ticket(l)?;
+            if nearby.len() != ticket.len() {
+                return Err(format!("line {}: ticket has {} fields, expected {}", first_nearby + i + 1, nearby.len(), ticket.len()));
+            }
+            Ok(nearby)
+        })
+        .collect::<Result<_, _>>()?;
 
-    (rules, ticket, nearby_tickets)
+    Ok((rules, ticket, nearby_tickets))
 }
 
-fn part1(data: &Vec<String>) -> usize {
-    let (rules, _, nearby_tickets) = parse_file(&data);
+fn part1(data: &[String]) -> Result<usize, String> {
+    let (rules, _, nearby_tickets) = parse_file(data)?;
 
-    let invalid_values = nearby_tickets.iter().map(|t| {
+    let invalid_values = nearby_tickets.iter().flat_map(|t| {
         // Filter down to the invalid values
-        t.iter()
-            .map(|v| {
-                if rules.iter().any(|r| r.includes_value(&v)) {
-                    Ok(v)
-                } else {
-                    Err(v)
-                }
-            })
-            .filter(|v| v.is_err())
-            .map(|v| v.unwrap_err())
-    }).flatten();
+        t.iter().filter(|v| !rules.iter().any(|r| r.includes_value(v)))
+    });
 
-    invalid_values.sum()
+    Ok(invalid_values.sum())
 }
 
-fn part2(data: &Vec<String>) -> usize {
-    let (rules, your_ticket, nearby_tickets) = parse_file(&data);
-
-    let valid_nearby_tickets: Vec<&Ticket> = nearby_tickets.iter().filter(|t| {
-        // Ticket is valid if all the fields match any rule
-        t.iter().all(|v| rules.iter().any(|r| r.includes_value(&v)))
-    }).collect();
+fn is_valid_ticket(rules: &[Rule], ticket: &[usize]) -> bool {
+    // Ticket is valid if all the fields match any rule
+    ticket.iter().all(|v| rules.iter().any(|r| r.includes_value(v)))
+}
 
-    let mut rules_with_possible_matches: Vec<(&Rule, Vec<usize>)> = rules.iter()
-        .map(|r| {
-            // Find the column index that matches this rule. In order for it to match, all tickets
-            // must match the rule at the current index
-            let matching_columns: Vec<usize> = (0..your_ticket.len()).filter(|i|
-                // Is it the i'th column?
-                valid_nearby_tickets.iter().all(|t| r.includes_value(&t[*i]))
-            ).collect();
+// The columns each rule could be, i.e. the columns where every ticket's value fits the rule
+fn candidate_columns(rules: &[Rule], tickets: &[&Ticket], columns: usize) -> Vec<Vec<usize>> {
+    rules.iter()
+        .map(|r| (0..columns).filter(|i| tickets.iter().all(|t| r.includes_value(&t[*i]))).collect())
+        .collect()
+}
 
-            (r, matching_columns)
-        }).collect();
+// Every way of giving each rule a different column out of its candidates, as the column for each
+// rule, stopping after limit of them. Backtracks on the rule with the fewest columns left each time.
+fn assignments(candidates: &[Vec<usize>], limit: usize) -> Vec<Vec<usize>> {
+    fn search(candidates: &[Vec<usize>], assigned: &mut Vec<Option<usize>>, used: &mut Vec<bool>, limit: usize, found: &mut Vec<Vec<usize>>) {
+        if found.len() >= limit {
+            return;
+        }
 
-    let mut solved_rules: Vec<String> = vec![];
+        let next = (0..candidates.len())
+            .filter(|r| assigned[*r].is_none())
+            .min_by_key(|r| candidates[*r].iter().filter(|c| !used[**c]).count());
+        let rule = match next {
+            Some(rule) => rule,
+            None => {
+                found.push(assigned.iter().map(|c| c.unwrap()).collect());
+                return;
+            }
+        };
+
+        for column in &candidates[rule] {
+            if !used[*column] {
+                used[*column] = true;
+                assigned[rule] = Some(*column);
+                search(candidates, assigned, used, limit, found);
+                assigned[rule] = None;
+                used[*column] = false;
+            }
+        }
+    }
 
-    // Keep looping until we solve all the departure rules
-    while solved_rules.iter().filter(|r| r.starts_with("departure")).count() != 6 {
-        let (matched_rule, matched_indexes) = rules_with_possible_matches.iter()
-            .filter(|(r, _)| solved_rules.iter().find(|sr| sr == &&r.name).is_none())
-            .find(|(_, v)| v.len() == 1).unwrap().clone();
+    let columns = candidates.iter().flatten().max().map_or(0, |c| c + 1);
+    let mut found = vec![];
+    search(candidates, &mut vec![None; candidates.len()], &mut vec![false; columns], limit, &mut found);
 
-        let matched_index = matched_indexes[0]; // There's only one by definition, we're good
+    found
+}
 
-        println!("Found {} {}", matched_rule.name, matched_index);
-        solved_rules.push(matched_rule.name.clone());
+#[derive(Debug, PartialEq)]
+enum FieldAssignment {
+    Unique(Vec<usize>),
+    // All of them, or the first limit of them if there were more and truncated is set
+    Ambiguous { assignments: Vec<Vec<usize>>, truncated: bool },
+    Impossible,
+}
 
-        for (r, i) in rules_with_possible_matches.iter_mut() {
-            if r.name != matched_rule.name {
-                i.retain(|v| v != &matched_index)
-            }
+fn assign_fields(rules: &[Rule], your_ticket: &[usize], nearby_tickets: &[Ticket], limit: usize) -> FieldAssignment {
+    let valid_nearby_tickets: Vec<&Ticket> = nearby_tickets.iter().filter(|t| is_valid_ticket(rules, t)).collect();
+    let candidates = candidate_columns(rules, &valid_nearby_tickets, your_ticket.len());
+
+    // One more than asked for is enough to tell a unique answer from an ambiguous one
+    let mut found = assignments(&candidates, limit.max(1) + 1);
+    match found.len() {
+        0 => FieldAssignment::Impossible,
+        1 => FieldAssignment::Unique(found.remove(0)),
+        _ => {
+            let truncated = found.len() > limit.max(1);
+            found.truncate(limit.max(1));
+            FieldAssignment::Ambiguous { assignments: found, truncated }
         }
     }
+}
 
-    rules_with_possible_matches.iter()
-        .filter(|(r, _)| r.name.starts_with("departure"))
-        .map(|(_, i)| your_ticket[i[0]]).product()
+fn format_assignment(rules: &[Rule], columns: &[usize]) -> String {
+    rules.iter().zip(columns).map(|(r, c)| format!("{}: {}", r.name, c)).collect::<Vec<String>>().join(", ")
+}
+
+// The product of your ticket's departure fields, however many there are
+fn part2(data: &[String]) -> Result<usize, String> {
+    let (rules, your_ticket, nearby_tickets) = parse_file(data)?;
+
+    match assign_fields(&rules, &your_ticket, &nearby_tickets, MAX_ASSIGNMENTS) {
+        FieldAssignment::Unique(columns) => Ok(rules.iter()
+            .zip(columns)
+            .filter(|(r, _)| r.name.starts_with("departure"))
+            .map(|(_, c)| your_ticket[c])
+            .product()),
+        FieldAssignment::Ambiguous { assignments: all, truncated } => Err(format!(
+            "{}{} assignments fit the tickets:\n{}",
+            if truncated { "at least " } else { "" },
+            all.len(),
+            all.iter().map(|a| format_assignment(&rules, a)).collect::<Vec<String>>().join("\n")
+        )),
+        FieldAssignment::Impossible => Err(String::from("no assignment of fields to columns fits the tickets")),
+    }
 }
 
 fn main() {
     let data: Vec<String> = utils::read_lines("./input_data/16.txt");
 
-    println!("Part 1: {}", part1(&data));
-    println!("Part 2: {}", part2(&data));
+    match part1(&data) {
+        Ok(sum) => println!("Part 1: {}", sum),
+        Err(e) => println!("Part 1: {}", e),
+    }
+    match part2(&data) {
+        Ok(product) => println!("Part 2: {}", product),
+        Err(e) => println!("Part 2: {}", e),
+    }
 }
 
 #[cfg(test)]
@@ -151,7 +318,7 @@ mod tests {
 
     #[test]
     fn test_example() {
-        let test_input: Vec<String> = vec![
+        let test_input: Vec<String> = [
             "class: 1-3 or 5-7",
             "row: 6-11 or 33-44",
             "seat: 13-40 or 45-50",
@@ -164,16 +331,16 @@ mod tests {
             "40,4,50",
             "55,2,20",
             "38,6,12"
-        ].iter().map(|s| String::from(s.clone())).collect();
+        ].iter().map(|s| String::from(*s)).collect();
 
-        let (rules, your_ticket, nearby_tickets) = parse_file(&test_input);
+        let (rules, your_ticket, nearby_tickets) = parse_file(&test_input).unwrap();
         assert_eq!(rules.len(), 3);
         assert_eq!(rules[1].name, "row");
         assert_eq!(rules[1].ranges.len(), 2);
-        assert_eq!(rules[1].ranges[0].start, 6);
-        assert_eq!(rules[1].ranges[0].end, 12);
-        assert_eq!(rules[1].ranges[1].start, 33);
-        assert_eq!(rules[1].ranges[1].end, 45);
+        assert_eq!(*rules[1].ranges[0].start(), 6);
+        assert_eq!(*rules[1].ranges[0].end(), 11);
+        assert_eq!(*rules[1].ranges[1].start(), 33);
+        assert_eq!(*rules[1].ranges[1].end(), 44);
 
         assert_eq!(your_ticket, [7, 1, 14]);
 
@@ -181,6 +348,124 @@ mod tests {
         assert_eq!(nearby_tickets[0], [7, 3, 47]);
         assert_eq!(nearby_tickets[1], [40, 4, 50]);
 
-        assert_eq!(part1(&test_input), 71);
+        assert_eq!(part1(&test_input), Ok(71));
+    }
+
+    fn rules(names: &[&str]) -> Vec<Rule> {
+        names.iter().map(|n| parse_rule(n).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_assign_fields() {
+        let rules = rules(&["class: 0-1 or 4-19", "row: 0-5 or 8-19", "seat: 0-13 or 16-19"]);
+        let nearby: Vec<Ticket> = vec![vec![3, 9, 18], vec![15, 1, 5], vec![5, 14, 9], vec![20, 1, 1]];
+        assert_eq!(assign_fields(&rules, &[11, 12, 13], &nearby, 10), FieldAssignment::Unique(vec![1, 0, 2]));
+
+        // Without the tickets, anything goes
+        assert_eq!(assignments(&[vec![0, 1], vec![0, 1]], 10), vec![vec![0, 1], vec![1, 0]]);
+        assert_eq!(
+            assign_fields(&rules, &[11, 12, 13], &[], 4),
+            FieldAssignment::Ambiguous {
+                assignments: assignments(&[vec![0, 1, 2], vec![0, 1, 2], vec![0, 1, 2]], 4),
+                truncated: true,
+            }
+        );
+        // Exactly as many as the limit isn't truncated
+        assert_eq!(
+            assign_fields(&rules, &[11, 12, 13], &[], 6),
+            FieldAssignment::Ambiguous {
+                assignments: assignments(&[vec![0, 1, 2], vec![0, 1, 2], vec![0, 1, 2]], 6),
+                truncated: false,
+            }
+        );
+        // Three fields don't fit in two columns
+        assert_eq!(assign_fields(&rules, &[11, 12], &[vec![3, 9]], 10), FieldAssignment::Impossible);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_assignments() {
+        // Both rules only fit column 0
+        assert!(assignments(&[vec![0], vec![0, 1], vec![0]], 10).is_empty());
+        // Greedy elimination has no single candidate to start from here
+        assert_eq!(assignments(&[vec![0, 1], vec![1, 2], vec![0, 2]], 10), vec![vec![0, 1, 2], vec![1, 2, 0]]);
+        assert_eq!(assignments(&[vec![0, 1], vec![0, 1], vec![0, 1, 2]], 10), vec![vec![0, 1, 2], vec![1, 0, 2]]);
+        assert_eq!(assignments(&[vec![0, 1, 2], vec![0, 1, 2], vec![0, 1, 2]], 4).len(), 4);
+        assert_eq!(assignments(&[], 10), vec![Vec::<usize>::new()]);
+    }
+
+    #[test]
+    fn test_part2_departures() {
+        let test_input: Vec<String> = [
+            "departure a: 0-1 or 4-19",
+            "row: 0-5 or 8-19",
+            "departure b: 0-13 or 16-19",
+            "",
+            "your ticket:",
+            "11,12,13",
+            "",
+            "nearby tickets:",
+            "3,9,18",
+            "15,1,5",
+            "5,14,9",
+        ].iter().map(|s| String::from(*s)).collect();
+        assert_eq!(part2(&test_input), Ok(12 * 13));
+
+        let bad_rule: Vec<String> = test_input.iter().map(|l| l.replace("0-5", "5-0")).collect();
+        assert_eq!(part1(&bad_rule), Err(String::from("range '5-0' is backwards")));
+        assert!(part2(&test_input[..3]).is_err());
+    }
+
+    #[test]
+    fn test_ticket_width() {
+        let test_input: Vec<String> = ["a: 1-10", "b: 1-10", "", "your ticket:", "1,2", "", "nearby tickets:", "3,4", "3"]
+            .iter()
+            .map(|s| String::from(*s))
+            .collect();
+        let expected = Err(String::from("line 9: ticket has 1 fields, expected 2"));
+        assert_eq!(parse_file(&test_input).map(|_| ()), expected);
+        assert_eq!(part2(&test_input).map(|_| ()), expected);
+    }
+
+    #[test]
+    fn test_rule_syntax() {
+        let rule = parse_rule("a: 1-3 or 7 or 2-5 or >= 30 or not 35-40").unwrap();
+        assert_eq!(rule.ranges, vec![1..=5, 7..=7, 30..=34, 41..=usize::MAX]);
+
+        let rule = parse_rule("b: not 10-20").unwrap();
+        assert_eq!(rule.ranges, vec![0..=9, 21..=usize::MAX]);
+
+        let rule = parse_rule("c: < 3 or > 8 or <= 5").unwrap();
+        assert_eq!(rule.ranges, vec![0..=5, 9..=usize::MAX]);
+
+        // Adjacent ranges merge too
+        assert_eq!(parse_rule("d: 1-2 or 3-4").unwrap().ranges, vec![1..=4]);
+        assert_eq!(parse_rule("e: 1-5 or not 1-5").unwrap().ranges, vec![]);
+
+        // The ends of usize
+        let max = usize::MAX;
+        assert_eq!(parse_rule(&format!("m: >= {}", max)).unwrap().ranges, vec![max..=max]);
+        assert_eq!(parse_rule(&format!("m: {}", max)).unwrap().ranges, vec![max..=max]);
+        assert_eq!(parse_rule(&format!("m: 5-{} or not {}", max, max)).unwrap().ranges, vec![5..=max - 1]);
+        assert_eq!(parse_rule(&format!("m: not >= {}", max - 1)).unwrap().ranges, vec![0..=max - 2]);
+        assert_eq!(parse_rule("m: 0 or 1-3 or not 0").unwrap().ranges, vec![1..=3]);
+        assert!(parse_rule(&format!("m: > {}", max)).is_err());
+        assert!(parse_rule("m: < 0").is_err());
+        assert!(parse_rule("m: 99999999999999999999999").is_err());
+
+        assert!(parse_rule("f: 5-1").is_err());
+        assert!(parse_rule("g: 1-2 and 3-4").is_err());
+        assert!(parse_rule("h 1-2").is_err());
+    }
+
+    #[test]
+    fn test_includes_value() {
+        let rule = parse_rule("a: 1-3 or 7 or 20-30 or not 25").unwrap();
+        let expected = |v: usize| (1..=3).contains(&v) || v == 7 || ((20..=30).contains(&v) && v != 25);
+        for v in 0..40 {
+            assert_eq!(rule.includes_value(&v), expected(v), "{}", v);
+        }
+        assert!(!parse_rule("b: 1 or not 1").unwrap().includes_value(&1));
+        assert!(parse_rule("c: >= 5").unwrap().includes_value(&usize::MAX));
+        assert!(!parse_rule("c: >= 5").unwrap().includes_value(&4));
+    }
+}
@@ -1,16 +1,32 @@
 extern crate aoc2020;
 
 use aoc2020::utils;
+use std::collections::HashSet;
+use std::env;
 
 fn parse_line(line: &str) -> (String, i32) {
-    let mut parts = line.split(" ");
+    let mut parts = line.split(' ');
     let op = parts.next().unwrap();
     let value = parts.next().unwrap().parse::<i32>().unwrap();
 
     (op.to_string(), value)
 }
 
-fn run_program(instructions: &Vec<(String, i32)>) -> Result<i32, i32> {
+// Executes a single instruction in a program of len instructions, returning the next pc and
+// accumulator. The pc is None if the instruction jumps outside 0..=len.
+fn execute(instruction: &(String, i32), pc: usize, acc: i32, len: usize) -> (Option<usize>, i32) {
+    let (op, value) = instruction;
+    let next = successor(op, *value, pc);
+    let next_pc = if next >= 0 && next <= len as i64 { Some(next as usize) } else { None };
+
+    if op == "acc" {
+        (next_pc, acc + value)
+    } else {
+        (next_pc, acc)
+    }
+}
+
+fn run_program(instructions: &[(String, i32)]) -> Result<i32, i32> {
     let mut executed_instructions: Vec<bool> = vec![false; instructions.len()];
 
     let mut acc = 0;
@@ -21,10 +37,6 @@ fn run_program(instructions: &Vec<(String, i32)>) -> Result<i32, i32> {
             return Ok(acc);
         }
 
-        let instruction = &instructions[pc];
-        let op = &instruction.0;
-        let value = instruction.1;
-
         if executed_instructions[pc] {
             // Executing the same instruction for a second time, we're in a cycle
             return Err(acc);
@@ -32,53 +44,682 @@ fn run_program(instructions: &Vec<(String, i32)>) -> Result<i32, i32> {
 
         executed_instructions[pc] = true;
 
-        if op == "jmp" {
-            pc = (pc as i32 + value) as usize;
-        } else {
-            pc += 1;
+        let (next_pc, next_acc) = execute(&instructions[pc], pc, acc, instructions.len());
+        pc = match next_pc {
+            Some(next_pc) => next_pc,
+            // Jumped out of the program, which doesn't count as finishing
+            None => return Err(next_acc),
+        };
+        acc = next_acc;
+    }
+}
+
+fn flip_op(op: &str) -> Option<&'static str> {
+    match op {
+        "jmp" => Some("nop"),
+        "nop" => Some("jmp"),
+        _ => None,
+    }
+}
+
+fn successor(op: &str, value: i32, pc: usize) -> i64 {
+    if op == "jmp" {
+        pc as i64 + value as i64
+    } else {
+        pc as i64 + 1
+    }
+}
+
+// Every pc from which the unmodified program runs off the end, found by walking the
+// successor edges backwards from the termination point
+fn find_terminating(instructions: &[(String, i32)]) -> Vec<bool> {
+    let end = instructions.len();
+    let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); end + 1];
+
+    for (pc, (op, value)) in instructions.iter().enumerate() {
+        let next = successor(op, *value, pc);
+        if next >= 0 && next <= end as i64 {
+            predecessors[next as usize].push(pc);
         }
+    }
+
+    let mut terminating = vec![false; end + 1];
+    terminating[end] = true;
 
-        if op == "acc" {
-            acc += value;
+    let mut stack = vec![end];
+    while let Some(pc) = stack.pop() {
+        for &previous in &predecessors[pc] {
+            if !terminating[previous] {
+                terminating[previous] = true;
+                stack.push(previous);
+            }
         }
     }
+
+    terminating
 }
 
-fn main() {
-    let mut instructions: Vec<(String, i32)> = utils::read_lines("./input_data/8.txt")
-        .iter().map(|l| parse_line(l)).collect();
+#[derive(Debug, PartialEq)]
+struct Repair {
+    // 1-based line number in the input
+    line: usize,
+    original_op: String,
+    new_op: String,
+    acc: i32,
+}
 
-    let part1 = run_program(&instructions);
-    println!("Part 1: {}", part1.unwrap_err());
+fn repair_program(instructions: &[(String, i32)]) -> Option<Repair> {
+    let terminating = find_terminating(instructions);
+    let end = instructions.len() as i64;
 
-    for i in 0..instructions.len() {
-        let swapped_op = instructions[i].0.clone();
+    // Walk the original path once, the first flip that lands in the terminating set is the fix
+    let mut visited = vec![false; instructions.len()];
+    let mut pc: usize = 0;
+    while pc < instructions.len() && !visited[pc] {
+        visited[pc] = true;
 
-        if swapped_op == "jmp" {
-            instructions[i].0 = String::from("nop")
-        } else if swapped_op == "nop" {
-            instructions[i].0 = String::from("jmp")
-        } else {
-            // skip acc ops
+        let (op, value) = &instructions[pc];
+        if let Some(new_op) = flip_op(op) {
+            let next = successor(new_op, *value, pc);
+            if next >= 0 && next <= end && terminating[next as usize] {
+                let mut patched = instructions.to_vec();
+                patched[pc].0 = new_op.to_string();
+
+                return run_program(&patched).ok().map(|acc| Repair {
+                    line: pc + 1,
+                    original_op: op.clone(),
+                    new_op: new_op.to_string(),
+                    acc,
+                });
+            }
+        }
+
+        let next = successor(op, *value, pc);
+        if next < 0 || next > end {
+            return None;
+        }
+        pc = next as usize;
+    }
+
+    None
+}
+
+#[derive(Debug, PartialEq)]
+struct TraceEntry {
+    pc: usize,
+    op: String,
+    arg: i32,
+    // Accumulator after the instruction has executed
+    acc: i32,
+}
+
+struct Trace {
+    entries: Vec<TraceEntry>,
+    result: Result<i32, i32>,
+    // The pc that would have been executed a second time, if the program looped
+    repeated_pc: Option<usize>,
+    // The pc of a jmp outside the program and where it was going, if the program crashed
+    crash: Option<(usize, i64)>,
+}
+
+impl Trace {
+    fn loop_segment(&self) -> &[TraceEntry] {
+        match self.repeated_pc {
+            Some(pc) => {
+                let start = self.entries.iter().position(|e| e.pc == pc).unwrap();
+                &self.entries[start..]
+            }
+            None => &[],
+        }
+    }
+}
+
+fn trace_program(instructions: &[(String, i32)]) -> Trace {
+    let mut executed_instructions: Vec<bool> = vec![false; instructions.len()];
+    let mut entries: Vec<TraceEntry> = Vec::new();
+
+    let mut acc = 0;
+    let mut pc: usize = 0;
+    loop {
+        if pc == instructions.len() {
+            return Trace { entries, result: Ok(acc), repeated_pc: None, crash: None };
+        }
+
+        if executed_instructions[pc] {
+            return Trace { entries, result: Err(acc), repeated_pc: Some(pc), crash: None };
+        }
+
+        executed_instructions[pc] = true;
+
+        let (next_pc, next_acc) = execute(&instructions[pc], pc, acc, instructions.len());
+        let (op, arg) = &instructions[pc];
+        entries.push(TraceEntry { pc, op: op.clone(), arg: *arg, acc: next_acc });
+
+        pc = match next_pc {
+            Some(next_pc) => next_pc,
+            None => {
+                let target = successor(op, *arg, pc);
+                return Trace { entries, result: Err(next_acc), repeated_pc: None, crash: Some((pc, target)) };
+            }
+        };
+        acc = next_acc;
+    }
+}
+
+fn format_entry(entry: &TraceEntry) -> String {
+    format!("{:>5}: {} {:+} acc={}", entry.pc, entry.op, entry.arg, entry.acc)
+}
+
+fn jump_target(instructions: &[(String, i32)], pc: usize) -> Option<usize> {
+    let (op, value) = &instructions[pc];
+    let target = successor(op, *value, pc);
+    if op == "jmp" && target >= 0 && target <= instructions.len() as i64 {
+        Some(target as usize)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct BasicBlock {
+    start: usize,
+    // Exclusive
+    end: usize,
+    // Start pcs of the following blocks, instructions.len() is the program exit
+    successors: Vec<usize>,
+}
+
+fn basic_blocks(instructions: &[(String, i32)]) -> Vec<BasicBlock> {
+    let mut leaders = vec![false; instructions.len() + 1];
+    leaders[0] = true;
+    leaders[instructions.len()] = true;
+
+    for pc in 0..instructions.len() {
+        if instructions[pc].0 == "jmp" {
+            leaders[pc + 1] = true;
+            if let Some(target) = jump_target(instructions, pc) {
+                leaders[target] = true;
+            }
+        }
+    }
+
+    let mut blocks: Vec<BasicBlock> = Vec::new();
+    let mut start = 0;
+    for (pc, &leader) in leaders.iter().enumerate().skip(1) {
+        if leader {
+            let last = pc - 1;
+            let successors = if instructions[last].0 == "jmp" {
+                jump_target(instructions, last).into_iter().collect()
+            } else {
+                vec![pc]
+            };
+
+            blocks.push(BasicBlock { start, end: pc, successors });
+            start = pc;
+        }
+    }
+
+    blocks
+}
+
+// Instructions reachable from pc 0 when running the program unmodified
+fn find_reachable(instructions: &[(String, i32)]) -> Vec<bool> {
+    let mut reachable = vec![false; instructions.len()];
+
+    let mut stack = vec![0];
+    while let Some(pc) = stack.pop() {
+        if pc >= instructions.len() || reachable[pc] {
             continue;
         }
+        reachable[pc] = true;
+
+        let (op, value) = &instructions[pc];
+        let next = successor(op, *value, pc);
+        if next >= 0 {
+            stack.push(next as usize);
+        }
+    }
+
+    reachable
+}
+
+fn find_loop(instructions: &[(String, i32)]) -> Vec<bool> {
+    let mut in_loop = vec![false; instructions.len()];
+    trace_program(instructions).loop_segment().iter().for_each(|e| in_loop[e.pc] = true);
+
+    in_loop
+}
+
+fn disassemble(instructions: &[(String, i32)]) -> Vec<String> {
+    let blocks = basic_blocks(instructions);
+    let reachable = find_reachable(instructions);
+    let in_loop = find_loop(instructions);
+
+    let mut labelled = vec![false; instructions.len() + 1];
+    for pc in 0..instructions.len() {
+        if let Some(target) = jump_target(instructions, pc) {
+            labelled[target] = true;
+        }
+    }
+
+    let mut listing: Vec<String> = Vec::new();
+    for block in &blocks {
+        if labelled[block.start] || block.start == 0 {
+            listing.push(format!("L{}:", block.start));
+        }
+
+        for pc in block.start..block.end {
+            let (op, value) = &instructions[pc];
+
+            let mut notes: Vec<String> = Vec::new();
+            if op == "jmp" {
+                notes.push(match jump_target(instructions, pc) {
+                    Some(target) if target == instructions.len() => String::from("-> exit"),
+                    Some(target) => format!("-> L{}", target),
+                    None => String::from("-> invalid"),
+                });
+            }
+            if in_loop[pc] {
+                notes.push(String::from("loop"));
+            }
+            if !reachable[pc] {
+                notes.push(String::from("unreachable"));
+            }
+
+            let line = format!("    {:>5}  {} {:+}", pc, op, value);
+            if notes.is_empty() {
+                listing.push(line);
+            } else {
+                listing.push(format!("{:<24}; {}", line, notes.join(", ")));
+            }
+        }
+    }
+
+    if labelled[instructions.len()] {
+        listing.push(format!("L{}:", instructions.len()));
+    }
+
+    listing
+}
+
+fn to_dot(instructions: &[(String, i32)]) -> String {
+    let reachable = find_reachable(instructions);
+    let in_loop = find_loop(instructions);
+
+    let mut dot = String::from("digraph program {\n    node [shape=box, fontname=monospace];\n");
+    dot.push_str("    exit [shape=doublecircle];\n");
+
+    for block in basic_blocks(instructions) {
+        let label: String = (block.start..block.end)
+            .map(|pc| format!("{}: {} {:+}\\l", pc, instructions[pc].0, instructions[pc].1))
+            .collect();
+
+        let style = if in_loop[block.start] {
+            ", color=red"
+        } else if !reachable[block.start] {
+            ", style=dashed, color=gray"
+        } else {
+            ""
+        };
+
+        dot.push_str(&format!("    b{} [label=\"{}\"{}];\n", block.start, label, style));
+
+        for next in block.successors {
+            if next == instructions.len() {
+                dot.push_str(&format!("    b{} -> exit;\n", block.start));
+            } else {
+                dot.push_str(&format!("    b{} -> b{};\n", block.start, next));
+            }
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
 
-        let result = run_program(&instructions);
-        if result.is_ok() {
-            println!("Part 2: {}", result.unwrap());
+#[derive(Debug, PartialEq)]
+enum DebuggerState {
+    Running,
+    Breakpoint,
+    Terminated,
+    Looped,
+    // The instruction at pc jumps to target, outside the program
+    Crashed { target: i64 },
+}
+
+struct Debugger<'a> {
+    instructions: &'a [(String, i32)],
+    pc: usize,
+    acc: i32,
+    executed_instructions: Vec<bool>,
+    breakpoints: HashSet<usize>,
+    state: DebuggerState,
+}
+
+impl<'a> Debugger<'a> {
+    fn new(instructions: &'a [(String, i32)]) -> Debugger<'a> {
+        Debugger {
+            instructions,
+            pc: 0,
+            acc: 0,
+            executed_instructions: vec![false; instructions.len()],
+            breakpoints: HashSet::new(),
+            // An empty program has already run off the end
+            state: if instructions.is_empty() { DebuggerState::Terminated } else { DebuggerState::Running },
+        }
+    }
+
+    fn is_halted(&self) -> bool {
+        matches!(self.state, DebuggerState::Terminated | DebuggerState::Looped | DebuggerState::Crashed { .. })
+    }
+
+    // Executes one instruction, updating the state if the program finishes or is about to loop
+    fn step(&mut self) {
+        if self.is_halted() {
+            return;
         }
 
-        instructions[i].0 = swapped_op.to_string();
+        let instruction = &self.instructions[self.pc];
+        let (next_pc, next_acc) = execute(instruction, self.pc, self.acc, self.instructions.len());
+        self.executed_instructions[self.pc] = true;
+        self.acc = next_acc;
+        self.pc = match next_pc {
+            Some(next_pc) => next_pc,
+            None => {
+                // Stay on the bad jump so it can be inspected
+                self.state = DebuggerState::Crashed { target: successor(&instruction.0, instruction.1, self.pc) };
+                return;
+            }
+        };
+
+        self.state = if self.pc == self.instructions.len() {
+            DebuggerState::Terminated
+        } else if self.executed_instructions[self.pc] {
+            DebuggerState::Looped
+        } else if self.breakpoints.contains(&self.pc) {
+            DebuggerState::Breakpoint
+        } else {
+            DebuggerState::Running
+        };
+    }
+
+    fn step_n(&mut self, count: usize) {
+        for _ in 0..count {
+            self.step();
+            if self.state != DebuggerState::Running {
+                break;
+            }
+        }
+    }
+
+    fn continue_running(&mut self) {
+        loop {
+            self.step();
+            if self.state != DebuggerState::Running {
+                break;
+            }
+        }
+    }
+
+    fn status(&self) -> String {
+        match self.state {
+            DebuggerState::Running => format!("pc={} acc={}", self.pc, self.acc),
+            DebuggerState::Breakpoint => format!("breakpoint pc={} acc={}", self.pc, self.acc),
+            DebuggerState::Terminated => format!("terminated acc={}", self.acc),
+            DebuggerState::Looped => format!("loop at pc={} acc={}", self.pc, self.acc),
+            DebuggerState::Crashed { target } => {
+                format!("crashed at pc={} jumping to {} acc={}", self.pc, target, self.acc)
+            }
+        }
+    }
+
+    // Runs a debugger script, one command per line, returning the output lines
+    //   break <pc>    set a breakpoint
+    //   delete <pc>   remove a breakpoint
+    //   step [n]      execute n instructions (default 1)
+    //   continue      run until a breakpoint, termination, a loop or a jump out of the program
+    //   acc / pc      print the accumulator / program counter
+    fn run_script(&mut self, script: &[String]) -> Result<Vec<String>, String> {
+        let mut output: Vec<String> = Vec::new();
+
+        for (line_number, line) in script.iter().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let command = parts.next().unwrap();
+            let arg = parts.next()
+                .map(|a| a.parse::<usize>()
+                    .map_err(|_| format!("line {}: invalid argument '{}'", line_number + 1, a)))
+                .transpose()?;
+
+            match (command, arg) {
+                ("break", Some(pc)) | ("b", Some(pc)) => {
+                    self.breakpoints.insert(pc);
+                }
+                ("delete", Some(pc)) | ("d", Some(pc)) => {
+                    self.breakpoints.remove(&pc);
+                }
+                ("step", count) | ("s", count) => {
+                    self.step_n(count.unwrap_or(1));
+                    output.push(self.status());
+                }
+                ("continue", None) | ("c", None) => {
+                    self.continue_running();
+                    output.push(self.status());
+                }
+                ("acc", None) => output.push(format!("acc={}", self.acc)),
+                ("pc", None) => output.push(format!("pc={}", self.pc)),
+                _ => return Err(format!("line {}: unknown command '{}'", line_number + 1, line)),
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+fn main() {
+    let instructions: Vec<(String, i32)> = utils::read_lines("./input_data/8.txt")
+        .iter().map(|l| parse_line(l)).collect();
+
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("trace") => {
+            let trace = trace_program(&instructions);
+            trace.entries.iter().for_each(|e| println!("{}", format_entry(e)));
+
+            match trace.result {
+                Ok(acc) => println!("Terminated: {}", acc),
+                Err(acc) if trace.crash.is_some() => {
+                    let (pc, target) = trace.crash.unwrap();
+                    println!("Crashed (acc {}): pc {} jumps to {}", acc, pc, target);
+                }
+                Err(acc) => {
+                    println!("Loop (acc {}):", acc);
+                    trace.loop_segment().iter().for_each(|e| println!("{}", format_entry(e)));
+                }
+            }
+            return;
+        }
+        Some("debug") => {
+            let script = utils::read_lines(args.get(2).expect("usage: 8 debug <script>"));
+            match Debugger::new(&instructions).run_script(&script) {
+                Ok(output) => output.iter().for_each(|l| println!("{}", l)),
+                Err(e) => eprintln!("{}", e),
+            }
+            return;
+        }
+        Some("disasm") => {
+            disassemble(&instructions).iter().for_each(|l| println!("{}", l));
+            return;
+        }
+        Some("dot") => {
+            print!("{}", to_dot(&instructions));
+            return;
+        }
+        _ => {}
     }
+
+    let part1 = run_program(&instructions);
+    println!("Part 1: {}", part1.unwrap_err());
+
+    let repair = repair_program(&instructions).expect("no single jmp/nop flip terminates");
+    println!("Patched line {}: {} -> {}", repair.line, repair.original_op, repair.new_op);
+    println!("Part 2: {}", repair.acc);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn example() -> Vec<(String, i32)> {
+        [
+            "nop +0",
+            "acc +1",
+            "jmp +4",
+            "acc +3",
+            "jmp -3",
+            "acc -99",
+            "acc +1",
+            "jmp -4",
+            "acc +6"
+        ].iter().map(|l| parse_line(l)).collect()
+    }
+
+    fn script(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|s| s.to_string()).collect()
+    }
+
     #[test]
     fn test_examples() {
-        assert_eq!(parse_line("jmp -150"), ("jmp", -150));
-        assert_eq!(parse_line("acc +41"), ("acc", 41));
+        assert_eq!(parse_line("jmp -150"), (String::from("jmp"), -150));
+        assert_eq!(parse_line("acc +41"), (String::from("acc"), 41));
+    }
+
+    #[test]
+    fn test_trace() {
+        let trace = trace_program(&example());
+        assert_eq!(trace.result, Err(5));
+        assert_eq!(trace.repeated_pc, Some(1));
+        assert_eq!(trace.entries.iter().map(|e| e.pc).collect::<Vec<usize>>(), vec![0, 1, 2, 6, 7, 3, 4]);
+
+        let loop_pcs: Vec<usize> = trace.loop_segment().iter().map(|e| e.pc).collect();
+        assert_eq!(loop_pcs, vec![1, 2, 6, 7, 3, 4]);
+        assert_eq!(trace.entries[3], TraceEntry { pc: 6, op: String::from("acc"), arg: 1, acc: 2 });
+    }
+
+    #[test]
+    fn test_trace_terminates() {
+        let mut instructions = example();
+        instructions[7].0 = String::from("nop");
+
+        let trace = trace_program(&instructions);
+        assert_eq!(trace.result, Ok(8));
+        assert!(trace.loop_segment().is_empty());
+    }
+
+    #[test]
+    fn test_repair() {
+        assert_eq!(repair_program(&example()), Some(Repair {
+            line: 8,
+            original_op: String::from("jmp"),
+            new_op: String::from("nop"),
+            acc: 8,
+        }));
+    }
+
+    #[test]
+    fn test_repair_impossible() {
+        let instructions: Vec<(String, i32)> = ["acc +1", "jmp -1", "jmp -2"]
+            .iter().map(|l| parse_line(l)).collect();
+        assert_eq!(repair_program(&instructions), None);
+    }
+
+    #[test]
+    fn test_basic_blocks() {
+        let blocks = basic_blocks(&example());
+        assert_eq!(blocks, vec![
+            BasicBlock { start: 0, end: 1, successors: vec![1] },
+            BasicBlock { start: 1, end: 3, successors: vec![6] },
+            BasicBlock { start: 3, end: 5, successors: vec![1] },
+            BasicBlock { start: 5, end: 6, successors: vec![6] },
+            BasicBlock { start: 6, end: 8, successors: vec![3] },
+            BasicBlock { start: 8, end: 9, successors: vec![9] },
+        ]);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let listing = disassemble(&example());
+        assert_eq!(listing[0], "L0:");
+        assert!(listing.contains(&String::from("L1:")));
+        assert!(listing.iter().any(|l| l.contains("jmp +4") && l.contains("-> L6") && l.contains("loop")));
+        assert!(listing.iter().any(|l| l.contains("acc -99") && l.contains("unreachable")));
+        assert!(listing.iter().any(|l| l.contains("acc +6") && l.contains("unreachable")));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let dot = to_dot(&example());
+        assert!(dot.starts_with("digraph program {"));
+        assert!(dot.contains("b1 -> b6;"));
+        assert!(dot.contains("b8 -> exit;"));
+    }
+
+    #[test]
+    fn test_debugger() {
+        let instructions = example();
+        let output = Debugger::new(&instructions).run_script(&script(&[
+            "# stop when we jump back",
+            "break 3",
+            "step 2",
+            "acc",
+            "continue",
+            "pc",
+            "continue",
+        ])).unwrap();
+
+        assert_eq!(output, vec![
+            "pc=2 acc=1",
+            "acc=1",
+            "breakpoint pc=3 acc=2",
+            "pc=3",
+            "loop at pc=1 acc=5",
+        ]);
+    }
+
+    #[test]
+    fn test_debugger_errors() {
+        let instructions = example();
+        assert!(Debugger::new(&instructions).run_script(&script(&["jump 3"])).is_err());
+        assert!(Debugger::new(&instructions).run_script(&script(&["break x"])).is_err());
+    }
+
+    #[test]
+    fn test_jump_out_of_program() {
+        let instructions = vec![(String::from("acc"), 2), (String::from("nop"), 0), (String::from("jmp"), -5)];
+        assert_eq!(run_program(&instructions), Err(2));
+
+        let trace = trace_program(&instructions);
+        assert_eq!(trace.result, Err(2));
+        assert_eq!(trace.crash, Some((2, -3)));
+        assert_eq!(trace.entries.len(), 3);
+        assert!(trace.loop_segment().is_empty());
+
+        let output = Debugger::new(&instructions).run_script(&script(&["continue", "step", "pc"])).unwrap();
+        assert_eq!(output, vec!["crashed at pc=2 jumping to -3 acc=2", "crashed at pc=2 jumping to -3 acc=2", "pc=2"]);
+
+        // One past the end is termination, further is a crash
+        let instructions = vec![(String::from("jmp"), 2)];
+        assert_eq!(trace_program(&instructions).crash, Some((0, 2)));
+        assert_eq!(trace_program(&[(String::from("jmp"), 1)]).result, Ok(0));
+    }
+
+    #[test]
+    fn test_debugger_empty_program() {
+        let output = Debugger::new(&[]).run_script(&script(&["step", "continue"])).unwrap();
+        assert_eq!(output, vec!["terminated acc=0", "terminated acc=0"]);
     }
 }
@@ -1,44 +1,276 @@
 extern crate aoc2020;
 
 use aoc2020::utils;
-use itertools::Itertools;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::ops::Range;
 
-fn is_sum_in_slice(sum: usize, slice: &[usize]) -> bool {
-    slice.iter().combinations(2).find(|pair| pair[0] + pair[1] == sum).is_some()
+// The last `preamble` numbers, along with a count of every sum of two distinct numbers in them,
+// so checking a number is O(1) and sliding the window along is O(preamble)
+struct XmasWindow {
+    window: VecDeque<usize>,
+    sums: HashMap<usize, usize>,
 }
 
-fn find_invalid_index(numbers: &Vec<usize>) -> usize {
-    for i in 25..numbers.len() {
-        if !is_sum_in_slice(numbers[i], &numbers[i - 25..i]) {
-            return i;
+impl XmasWindow {
+    fn new(preamble: &[usize]) -> XmasWindow {
+        let mut xmas_window = XmasWindow {
+            window: VecDeque::with_capacity(preamble.len()),
+            sums: HashMap::new(),
+        };
+
+        for n in preamble {
+            xmas_window.add(*n);
+        }
+
+        xmas_window
+    }
+
+    fn add(&mut self, n: usize) {
+        for other in self.window.iter().filter(|o| **o != n) {
+            *self.sums.entry(n + other).or_insert(0) += 1;
+        }
+
+        self.window.push_back(n);
+    }
+
+    fn remove_oldest(&mut self) {
+        let n = match self.window.pop_front() {
+            Some(n) => n,
+            None => return,
+        };
+
+        for other in self.window.iter().filter(|o| **o != n) {
+            let count = self.sums.get_mut(&(n + other)).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.sums.remove(&(n + other));
+            }
+        }
+    }
+
+    fn is_valid(&self, n: usize) -> bool {
+        self.sums.contains_key(&n)
+    }
+
+    fn push(&mut self, n: usize) {
+        self.remove_oldest();
+        self.add(n);
+    }
+}
+
+fn find_invalid_indices(numbers: &[usize], preamble: usize) -> Vec<usize> {
+    if numbers.len() <= preamble {
+        return Vec::new();
+    }
+
+    // With nothing before them, no number can be a sum of two previous ones
+    if preamble == 0 {
+        return (0..numbers.len()).collect();
+    }
+
+    let mut window = XmasWindow::new(&numbers[0..preamble]);
+    let mut invalid: Vec<usize> = Vec::new();
+
+    for (i, n) in numbers.iter().enumerate().skip(preamble) {
+        if !window.is_valid(*n) {
+            invalid.push(i);
+        }
+        window.push(*n);
+    }
+
+    invalid
+}
+
+// Two pointer search, the numbers are all positive so growing the window only ever increases the sum
+fn find_run_with_sum(sum: usize, numbers: &[usize]) -> Option<Range<usize>> {
+    let mut start = 0;
+    let mut end = 0;
+    let mut run_sum = 0;
+
+    loop {
+        if run_sum == sum && end - start >= 2 {
+            return Some(start..end);
+        }
+
+        if run_sum <= sum && end < numbers.len() {
+            run_sum += numbers[end];
+            end += 1;
+        } else if start < end {
+            run_sum -= numbers[start];
+            start += 1;
+        } else {
+            return None;
         }
     }
+}
 
-    panic!();
+fn weakness(numbers: &[usize], run: &Range<usize>) -> usize {
+    let slice = &numbers[run.clone()];
+    slice.iter().min().unwrap() + slice.iter().max().unwrap()
 }
 
-fn find_run_with_sum(sum: usize, slice: &[usize]) -> &[usize] {
-    for i in 0..slice.len() {
-        for j in (i + 1)..slice.len() {
-            let run = &slice[i..j];
-            if run.iter().sum::<usize>() == sum {
-                return run;
+// Every run of at least two numbers adding up to sum, along with its weakness
+fn find_all_runs_with_sum(sum: usize, numbers: &[usize]) -> Vec<(Range<usize>, usize)> {
+    // prefix_sums[i] is the sum of numbers[0..i], and a run i..j sums to prefix_sums[j] - prefix_sums[i]
+    let mut starts_by_prefix: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut runs: Vec<(Range<usize>, usize)> = Vec::new();
+
+    let mut prefix_sum = 0;
+    for end in 0..=numbers.len() {
+        if end >= 2 {
+            // Only starts at least two numbers back are allowed
+            let start = end - 2;
+            let start_prefix: usize = prefix_sum - numbers[start] - numbers[start + 1];
+            starts_by_prefix.entry(start_prefix).or_default().push(start);
+        }
+
+        if prefix_sum >= sum {
+            if let Some(starts) = starts_by_prefix.get(&(prefix_sum - sum)) {
+                for start in starts {
+                    let run = *start..end;
+                    let run_weakness = weakness(numbers, &run);
+                    runs.push((run, run_weakness));
+                }
             }
         }
+
+        if end < numbers.len() {
+            prefix_sum += numbers[end];
+        }
     }
 
-    panic!();
+    runs
+}
+
+fn parse_numbers(lines: &[String]) -> Result<Vec<usize>, String> {
+    lines.iter()
+        .enumerate()
+        .map(|(i, l)| l.parse::<usize>().map_err(|e| format!("line {}: bad number '{}': {}", i + 1, l, e)))
+        .collect()
 }
 
 fn main() {
-    let numbers: Vec<usize> = utils::read_lines("./input_data/9.txt")
-        .iter().map(|l| l.parse::<usize>().unwrap()).collect();
+    // e.g. "--preamble 5 --input example.txt --all-runs"
+    let args: Vec<String> = env::args().collect();
+    let preamble = match utils::parse_arg::<usize>(&args, "--preamble") {
+        Ok(preamble) => preamble.unwrap_or(25),
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let path = utils::arg_value(&args, "--input").unwrap_or("./input_data/9.txt");
+
+    let numbers = match parse_numbers(&utils::read_lines(path)) {
+        Ok(numbers) => numbers,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let invalid_indices = find_invalid_indices(&numbers, preamble);
+    for i in &invalid_indices {
+        println!("Invalid: {} (index {})", numbers[*i], i);
+    }
 
-    let invalid_index = find_invalid_index(&numbers);
+    let invalid_index = match invalid_indices.first() {
+        Some(i) => *i,
+        None => {
+            println!("No invalid numbers");
+            return;
+        }
+    };
     println!("Part 1: {}", numbers[invalid_index]);
 
-    let run = find_run_with_sum(numbers[invalid_index], &numbers[0..invalid_index]);
-    println!("run: {:?}", run);
+    match find_run_with_sum(numbers[invalid_index], &numbers[0..invalid_index]) {
+        Some(run) => {
+            println!("run: {:?}", &numbers[run.clone()]);
+            println!("Part 2: {}", weakness(&numbers, &run));
+        }
+        None => println!("No run adds up to {}", numbers[invalid_index]),
+    }
+
+    if args.iter().any(|a| a == "--all-runs") {
+        for (run, run_weakness) in find_all_runs_with_sum(numbers[invalid_index], &numbers) {
+            println!("{:?}: weakness {}", run, run_weakness);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Vec<usize> {
+        vec![35, 20, 15, 25, 47, 40, 62, 55, 65, 95, 102, 117, 150, 182, 127, 219, 299, 277, 309, 576]
+    }
+
+    #[test]
+    fn test_window() {
+        let mut window = XmasWindow::new(&(1..=25).collect::<Vec<usize>>());
+        assert!(window.is_valid(26));
+        assert!(window.is_valid(49));
+        assert!(!window.is_valid(100));
+        assert!(!window.is_valid(50));
+
+        window.push(45);
+        assert!(window.is_valid(26));
+        assert!(!window.is_valid(3));
+        assert!(window.is_valid(70));
+    }
+
+    #[test]
+    fn test_parse_numbers() {
+        assert_eq!(parse_numbers(&[String::from("1"), String::from("20")]), Ok(vec![1, 20]));
+        assert!(parse_numbers(&[String::from("1"), String::from("x")]).unwrap_err().starts_with("line 2:"));
+    }
+
+    #[test]
+    fn test_empty_preamble() {
+        assert_eq!(find_invalid_indices(&[1, 2, 3], 0), vec![0, 1, 2]);
+
+        let mut window = XmasWindow::new(&[]);
+        window.remove_oldest();
+        window.push(5);
+        assert!(!window.is_valid(5));
+    }
 
-    println!("Part 2: {}", run.iter().min().unwrap() + run.iter().max().unwrap())
+    #[test]
+    fn test_same_number_twice() {
+        let window = XmasWindow::new(&[5, 5, 1]);
+        assert!(!window.is_valid(10));
+        assert!(window.is_valid(6));
+    }
+
+    #[test]
+    fn test_example() {
+        assert_eq!(find_invalid_indices(&example(), 5), vec![14]);
+    }
+
+    #[test]
+    fn test_run_with_sum() {
+        assert_eq!(find_run_with_sum(127, &example()), Some(2..6));
+        assert_eq!(weakness(&example(), &(2..6)), 62);
+        assert_eq!(find_run_with_sum(1, &[1, 2, 3]), None);
+        assert_eq!(find_run_with_sum(5, &[5, 1, 2, 3]), Some(2..4));
+        assert_eq!(find_run_with_sum(1000, &example()), None);
+    }
+
+    #[test]
+    fn test_all_runs_with_sum() {
+        assert_eq!(find_all_runs_with_sum(127, &example()), vec![(2..6, 62)]);
+        assert_eq!(find_all_runs_with_sum(6, &[1, 2, 3, 3, 6, 3, 3]), vec![
+            (0..3, 4),
+            (2..4, 6),
+            (5..7, 6),
+        ]);
+        assert!(find_all_runs_with_sum(3, &[3]).is_empty());
+    }
+
+    #[test]
+    fn test_short_input() {
+        assert!(find_invalid_indices(&[1, 2, 3], 5).is_empty());
+    }
 }
@@ -0,0 +1,336 @@
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+// The operations the number theory helpers need, implemented for all the primitive integers
+pub trait Integer:
+    Copy
+    + PartialEq
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Rem<Output = Self>
+{
+    fn zero() -> Self;
+    fn one() -> Self;
+    fn abs(self) -> Self;
+}
+
+// Extended Euclid produces negative coefficients, so some helpers need signed integers
+pub trait Signed: Integer + Neg<Output = Self> {}
+
+macro_rules! impl_unsigned {
+    ($($t:ty),*) => {
+        $(
+            impl Integer for $t {
+                fn zero() -> Self { 0 }
+                fn one() -> Self { 1 }
+                fn abs(self) -> Self { self }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_signed {
+    ($($t:ty),*) => {
+        $(
+            impl Integer for $t {
+                fn zero() -> Self { 0 }
+                fn one() -> Self { 1 }
+                fn abs(self) -> Self { <$t>::abs(self) }
+            }
+
+            impl Signed for $t {}
+        )*
+    };
+}
+
+impl_unsigned!(u8, u16, u32, u64, u128, usize);
+impl_signed!(i8, i16, i32, i64, i128, isize);
+
+// Always non-negative, gcd(0, 0) is 0
+pub fn gcd<T: Integer>(a: T, b: T) -> T {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != T::zero() {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+
+    a
+}
+
+// Always non-negative, 0 if either is 0
+pub fn lcm<T: Integer>(a: T, b: T) -> T {
+    if a == T::zero() || b == T::zero() {
+        return T::zero();
+    }
+
+    (a / gcd(a, b) * b).abs()
+}
+
+// Returns (g, x, y) with a * x + b * y = g = gcd(a, b)
+pub fn ext_gcd<T: Signed>(a: T, b: T) -> (T, T, T) {
+    let (mut old_r, mut r) = (a, b);
+    let (mut old_x, mut x) = (T::one(), T::zero());
+    let (mut old_y, mut y) = (T::zero(), T::one());
+
+    while r != T::zero() {
+        let q = old_r / r;
+        let next_r = old_r - q * r;
+        old_r = r;
+        r = next_r;
+
+        let next_x = old_x - q * x;
+        old_x = x;
+        x = next_x;
+
+        let next_y = old_y - q * y;
+        old_y = y;
+        y = next_y;
+    }
+
+    if old_r < T::zero() {
+        (-old_r, -old_x, -old_y)
+    } else {
+        (old_r, old_x, old_y)
+    }
+}
+
+// a mod m in the range 0..m, for a positive m
+pub fn rem_euclid<T: Integer>(a: T, m: T) -> T {
+    let r = a % m;
+    if r < T::zero() {
+        r + m
+    } else {
+        r
+    }
+}
+
+// x with a * x = 1 (mod m), if a and m are coprime
+pub fn mod_inv<T: Signed>(a: T, m: T) -> Option<T> {
+    let (g, x, _) = ext_gcd(rem_euclid(a, m), m);
+    if g == T::one() {
+        Some(rem_euclid(x, m))
+    } else {
+        None
+    }
+}
+
+// base^exp mod m by squaring, (m - 1)^2 has to fit in T
+pub fn mod_pow<T: Integer>(base: T, exp: T, m: T) -> T {
+    let two = T::one() + T::one();
+    let mut result = T::one() % m;
+    let mut base = rem_euclid(base, m);
+    let mut exp = exp;
+
+    while exp > T::zero() {
+        if exp % two == T::one() {
+            result = result * base % m;
+        }
+        base = base * base % m;
+        exp = exp / two;
+    }
+
+    result
+}
+
+// Combines congruences t = r (mod m) into a single (r, m) with 0 <= r < m, or None if no t
+// satisfies them all. The moduli don't have to be coprime. The combined modulus times the
+// next one has to fit in T.
+pub fn crt<T: Signed>(congruences: &[(T, T)]) -> Option<(T, T)> {
+    congruences.iter().try_fold((T::zero(), T::one()), |(r1, m1), (r2, m2)| {
+        let (r2, m2) = (*r2, *m2);
+        let (g, p, _) = ext_gcd(m1, m2);
+        if (r2 - r1) % g != T::zero() {
+            return None;
+        }
+
+        // p is the inverse of m1 / g modulo m2 / g
+        let step = m2 / g;
+        let k = rem_euclid((r2 - r1) / g % step * (p % step), step);
+        let lcm = m1 * step;
+
+        Some((rem_euclid(r1 + m1 * k, lcm), lcm))
+    })
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cycle {
+    // Index of the first value that is part of the cycle
+    pub start: usize,
+    pub length: usize,
+}
+
+// Floyd's tortoise and hare over x0, f(x0), f(f(x0)), ... which must eventually repeat
+pub fn floyd<T: PartialEq + Clone, F: Fn(&T) -> T>(x0: T, f: F) -> Cycle {
+    let mut tortoise = f(&x0);
+    let mut hare = f(&tortoise);
+    while tortoise != hare {
+        tortoise = f(&tortoise);
+        hare = f(&f(&hare));
+    }
+
+    let mut start = 0;
+    tortoise = x0;
+    while tortoise != hare {
+        tortoise = f(&tortoise);
+        hare = f(&hare);
+        start += 1;
+    }
+
+    let mut length = 1;
+    hare = f(&tortoise);
+    while tortoise != hare {
+        hare = f(&hare);
+        length += 1;
+    }
+
+    Cycle { start, length }
+}
+
+// Brent's algorithm, the same result as floyd with fewer calls to f
+pub fn brent<T: PartialEq + Clone, F: Fn(&T) -> T>(x0: T, f: F) -> Cycle {
+    let mut power = 1;
+    let mut length = 1;
+    let mut tortoise = x0.clone();
+    let mut hare = f(&x0);
+    while tortoise != hare {
+        if power == length {
+            tortoise = hare.clone();
+            power *= 2;
+            length = 0;
+        }
+        hare = f(&hare);
+        length += 1;
+    }
+
+    tortoise = x0.clone();
+    hare = x0;
+    for _ in 0..length {
+        hare = f(&hare);
+    }
+
+    let mut start = 0;
+    while tortoise != hare {
+        tortoise = f(&tortoise);
+        hare = f(&hare);
+        start += 1;
+    }
+
+    Cycle { start, length }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brute_gcd(a: i64, b: i64) -> i64 {
+        (1..=a.abs().max(b.abs())).rev().find(|d| a % d == 0 && b % d == 0).unwrap_or(0)
+    }
+
+    #[test]
+    fn test_gcd_lcm() {
+        for a in -30i64..=30 {
+            for b in -30i64..=30 {
+                let g = gcd(a, b);
+                assert_eq!(g, brute_gcd(a, b), "gcd({}, {})", a, b);
+
+                let l = lcm(a, b);
+                if a == 0 || b == 0 {
+                    assert_eq!(l, 0);
+                } else {
+                    let brute = (1..).map(|k| k * a.abs()).find(|m| m % b == 0).unwrap();
+                    assert_eq!(l, brute, "lcm({}, {})", a, b);
+                }
+            }
+        }
+
+        assert_eq!(gcd(12u8, 18u8), 6);
+        assert_eq!(lcm(4usize, 6usize), 12);
+        assert_eq!(gcd(0u128, 0u128), 0);
+    }
+
+    #[test]
+    fn test_ext_gcd() {
+        for a in -40i32..=40 {
+            for b in -40i32..=40 {
+                let (g, x, y) = ext_gcd(a, b);
+                assert_eq!(g, gcd(a, b));
+                assert_eq!(a * x + b * y, g, "ext_gcd({}, {})", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mod_inv() {
+        for m in 1i64..=40 {
+            for a in -40i64..=40 {
+                let brute = (0..m).find(|x| rem_euclid(a * x, m) == 1 % m);
+                assert_eq!(mod_inv(a, m), brute, "mod_inv({}, {})", a, m);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mod_pow() {
+        for m in 1u64..=20 {
+            for base in 0u64..=20 {
+                let mut brute = 1 % m;
+                for exp in 0u64..=20 {
+                    assert_eq!(mod_pow(base, exp, m), brute, "mod_pow({}, {}, {})", base, exp, m);
+                    brute = brute * base % m;
+                }
+            }
+        }
+
+        assert_eq!(mod_pow(-2i32, 3, 5), 2);
+        assert_eq!(mod_pow(2u128, 127, u64::MAX as u128), 9_223_372_036_854_775_808 % u64::MAX as u128);
+    }
+
+    #[test]
+    fn test_crt() {
+        for m1 in 1i64..=12 {
+            for m2 in 1i64..=12 {
+                for r1 in 0..m1 {
+                    for r2 in 0..m2 {
+                        let l = lcm(m1, m2);
+                        let brute = (0..l).find(|t| t % m1 == r1 && t % m2 == r2).map(|t| (t, l));
+                        assert_eq!(crt(&[(r1, m1), (r2, m2)]), brute, "crt({} mod {}, {} mod {})", r1, m1, r2, m2);
+                    }
+                }
+            }
+        }
+
+        assert_eq!(crt::<i64>(&[]), Some((0, 1)));
+        assert_eq!(crt(&[(2i128, 3), (3, 5), (2, 7)]), Some((23, 105)));
+        assert_eq!(crt(&[(-1i32, 4)]), Some((3, 4)));
+    }
+
+    fn brute_cycle(x0: u64, f: impl Fn(&u64) -> u64) -> Cycle {
+        let mut seen: Vec<u64> = vec![x0];
+        loop {
+            let next = f(seen.last().unwrap());
+            if let Some(start) = seen.iter().position(|x| *x == next) {
+                return Cycle { start, length: seen.len() - start };
+            }
+            seen.push(next);
+        }
+    }
+
+    #[test]
+    fn test_cycle_detection() {
+        for m in 1u64..=30 {
+            for a in 0u64..=10 {
+                for c in 0u64..=5 {
+                    for x0 in [0u64, 1, 7, 100].iter() {
+                        let f = |x: &u64| (a * x * x + c) % m;
+                        let expected = brute_cycle(*x0, f);
+                        assert_eq!(floyd(*x0, f), expected, "floyd m={} a={} c={} x0={}", m, a, c, x0);
+                        assert_eq!(brent(*x0, f), expected, "brent m={} a={} c={} x0={}", m, a, c, x0);
+                    }
+                }
+            }
+        }
+    }
+}
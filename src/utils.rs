@@ -1,6 +1,8 @@
+use std::fmt::Display;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
+use std::str::FromStr;
 
 pub fn read_lines<P>(filename: P) -> Vec<String>
     where P: AsRef<Path>, {
@@ -30,3 +32,19 @@ pub fn read_groups<F: FnMut(&String) -> ()>(filename: &str, mut group_fn: F) {
     group_fn(&builder);
 }
 
+
+// The value following a "--name value" flag on the command line
+pub fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).map(String::as_str)
+}
+
+// The parsed value of a flag, None if it isn't given and an error if its value is missing or bad
+pub fn parse_arg<T: FromStr>(args: &[String], name: &str) -> Result<Option<T>, String>
+    where T::Err: Display, {
+    if !args.iter().any(|a| a == name) {
+        return Ok(None);
+    }
+
+    let value = arg_value(args, name).ok_or(format!("{} needs a value", name))?;
+    value.parse::<T>().map(Some).map_err(|e| format!("bad value '{}' for {}: {}", value, name, e))
+}